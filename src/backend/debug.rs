@@ -1,6 +1,7 @@
 use std::time::Instant;
 
 use crate::backend::ctx::Ctx;
+use crate::map::MapDebugData;
 use crate::player::PlayerDebugData;
 use wgpu::RenderPass;
 use wgpu_text::glyph_brush::ab_glyph::FontVec;
@@ -13,7 +14,8 @@ use super::ScissorRegion;
 
 pub struct DebugData {
     pub player_data: PlayerDebugData,
-    //pub world_data: WorldDebugData,
+    pub map_data: MapDebugData,
+    pub y_shearing: f32,
 }
 
 pub struct DebugUI {
@@ -52,6 +54,7 @@ impl DebugUI {
 
     pub fn update(&mut self, data: DebugData) {
         let player = data.player_data;
+        let map = data.map_data;
         let time_per_frame = 1000.0 / self.current_fps as f64;
         let data_str = format!(
             "FPS: {}\n\
@@ -60,7 +63,8 @@ impl DebugUI {
             Direction: Vec3({:.2}, {:.2}, {:.2})\n\
             Angle: {:.2} degrees\n\
             Y-shearing: {}\n\
-            Room: {} of {}\n\n\
+            Room: {} of {}\n\
+            Seed: {}\n\n\
             Fly: {}, Ghost: {}\n\
             On ground: {}\n\
             Velocity: x: {:.2}, z: {:.2}\n\
@@ -74,9 +78,10 @@ impl DebugUI {
             player.forward_dir.y,
             player.forward_dir.z,
             player.yaw_angle,
-            player.y_shearing,
+            data.y_shearing,
             player.current_room_id,
-            0, //data.world_data.room_count - 1,
+            map.room_count - 1,
+            map.seed,
             player.physics_state.can_fly,
             player.physics_state.is_ghost,
             player.physics_state.is_grounded,
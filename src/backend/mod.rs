@@ -1,6 +1,7 @@
 pub mod ctx;
 mod debug;
 
+pub use debug::DebugData;
 use debug::DebugUI;
 use pollster::block_on;
 use rayon::{iter::ParallelIterator, slice::ParallelSliceMut};
@@ -39,6 +40,7 @@ pub struct Canvas {
     size: wgpu::Extent3d,
 
     debug_ui: DebugUI,
+    show_debug_ui: bool,
 }
 
 impl Canvas {
@@ -220,6 +222,7 @@ impl Canvas {
             size,
 
             debug_ui,
+            show_debug_ui: true,
         }
     }
 
@@ -316,7 +319,9 @@ impl Canvas {
                 self.region.height,
             );
             rpass.draw(0..3, 0..1);
-            self.debug_ui.render(&mut rpass);
+            if self.show_debug_ui {
+                self.debug_ui.render(&mut rpass);
+            }
         }
 
         self.ctx.queue().submit(Some(encoder.finish()));
@@ -370,6 +375,21 @@ impl Canvas {
         self.debug_ui.resize(self.region, &self.ctx);
     }
 
+    pub fn update_debug_ui(&mut self, data: DebugData) {
+        self.debug_ui.update_frame_timings();
+        if !self.show_debug_ui {
+            return;
+        }
+        self.debug_ui.update(data);
+        if let Err(e) = self.debug_ui.queue_data(&self.ctx) {
+            log::error!("Failed to queue the debug UI: {}", e);
+        }
+    }
+
+    pub fn toggle_debug_ui(&mut self) {
+        self.show_debug_ui = !self.show_debug_ui;
+    }
+
     pub fn toggle_full_screen(&mut self) {
         self.ctx.toggle_full_screen();
     }
@@ -29,8 +29,10 @@ impl ControllerSettings {
             GameInput::FlashlightSwitch => KeyCode::KeyF,
             GameInput::ToggleSleepBetweenFrames => KeyCode::End,
             GameInput::ToggleFullScreen => KeyCode::F11,
+            GameInput::ToggleDebugUI => KeyCode::F3,
             GameInput::IncreaseResolution => KeyCode::PageUp,
             GameInput::DecreaseResolution => KeyCode::PageDown,
+            GameInput::CycleTextureFilter => KeyCode::KeyT,
             // TODO split controls into one reloading map, and other textures only
             GameInput::ReloadMap => KeyCode::KeyR
         }
@@ -69,7 +71,9 @@ pub enum GameInput {
     FlashlightSwitch,
     ToggleSleepBetweenFrames,
     ToggleFullScreen,
+    ToggleDebugUI,
     IncreaseResolution,
     DecreaseResolution,
+    CycleTextureFilter,
     ReloadMap
 }
@@ -13,7 +13,9 @@ use std::time::{Duration, Instant};
 
 use backend::Canvas;
 use control::{ControllerSettings, GameInput};
-use state::GameState;
+use map::validation::Severity;
+use map::{MapSettings, UnloadedLinks};
+use state::{GameState, LoadError};
 use winit::application::ApplicationHandler;
 use winit::event::{DeviceEvent, DeviceId, KeyEvent, StartCause};
 use winit::event_loop::ActiveEventLoop;
@@ -29,6 +31,7 @@ const CANVAS_WIDTH_FACTOR: u32 = 16;
 const CANVAS_HEIGHT_FACTOR: u32 = 9;
 const DEFAULT_CANVAS_WIDTH: u32 = 16 * 15;
 const DEFAULT_CANVAS_HEIGHT: u32 = 9 * 15;
+const SETTINGS_PATH: &str = "settings.txt";
 
 pub struct App {
     canvas: Option<Canvas>,
@@ -45,8 +48,8 @@ pub struct App {
 }
 
 impl App {
-    pub fn new() -> Self {
-        Self {
+    pub fn new(map_settings: MapSettings) -> Result<Self, LoadError> {
+        Ok(Self {
             canvas: None,
             controls: ControllerSettings::init(),
 
@@ -54,7 +57,8 @@ impl App {
                 "maps/map.txt",
                 DEFAULT_CANVAS_WIDTH,
                 DEFAULT_CANVAS_HEIGHT,
-            ),
+                map_settings,
+            )?,
 
             time_per_frame: Duration::from_secs_f64(1.0 / FPS_CAP as f64),
             now: Instant::now(),
@@ -62,7 +66,7 @@ impl App {
 
             acc_fps: 0,
             time: Instant::now(),
-        }
+        })
     }
 }
 
@@ -105,6 +109,9 @@ impl ApplicationHandler for App {
                                 GameInput::ToggleFullScreen if !is_pressed => {
                                     self.canvas.as_mut().unwrap().toggle_full_screen()
                                 }
+                                GameInput::ToggleDebugUI if !is_pressed => {
+                                    self.canvas.as_mut().unwrap().toggle_debug_ui()
+                                }
                                 GameInput::IncreaseResolution if !is_pressed => {
                                     let canvas = self.canvas.as_mut().unwrap();
                                     canvas.increase_resolution();
@@ -170,7 +177,11 @@ impl ApplicationHandler for App {
         if elapsed >= self.time_per_frame {
             self.now = Instant::now();
             // Update game
-            self.state.update(elapsed.as_secs_f32());
+            if let Err(e) = self.state.update(elapsed.as_secs_f32()) {
+                log::error!("Failed to generate the map: {}", e);
+                event_loop.exit();
+                return;
+            }
 
             if let Some(canvas) = self.canvas.as_mut() {
                 if self.time.elapsed().as_micros() >= 1_000_000 {
@@ -180,7 +191,12 @@ impl ApplicationHandler for App {
                 }
                 self.acc_fps += 1;
                 // First render game by pixel manipulation, ...
-                self.state.render(canvas.mut_column());
+                if let Err(e) = self.state.render(canvas.mut_column()) {
+                    log::error!("Failed to generate the map: {}", e);
+                    event_loop.exit();
+                    return;
+                }
+                canvas.update_debug_ui(self.state.collect_dbg_data());
                 // ... then request the screen redraw.
                 canvas.request_redraw();
             }
@@ -208,8 +224,110 @@ fn main() {
     }
     env_logger::init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("validate") {
+        let path = args.get(1).map(String::as_str).unwrap_or("tiled");
+        std::process::exit(validate(path));
+    }
+
+    // Same seed always generates the same world, the command line
+    // overrides the settings file
+    let seed = match args.iter().position(|arg| arg == "--seed") {
+        Some(i) => match args.get(i + 1).map(|seed| seed.parse::<u64>()) {
+            Some(Ok(seed)) => seed,
+            _ => {
+                log::error!("'--seed' expects a whole positive number");
+                std::process::exit(1);
+            }
+        },
+        None => match read_seed_setting(SETTINGS_PATH) {
+            Ok(seed) => seed.unwrap_or_else(rand::random),
+            Err(e) => {
+                log::error!("Failed to read '{}': {}", SETTINGS_PATH, e);
+                std::process::exit(1);
+            }
+        },
+    };
+    println!("World seed: {}", seed);
+    let mut map_settings = MapSettings::new(seed);
+    // Portals lead into new rooms once the rooms behind them are unloaded
+    if args.iter().any(|arg| arg == "--forget-unloaded-rooms") {
+        map_settings.unloaded_links = UnloadedLinks::Forget;
+    }
+
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
-    let mut state = App::new();
+    let mut state = match App::new(map_settings) {
+        Ok(state) => state,
+        Err(e) => {
+            log::error!("Failed to load the map: {}", e);
+            std::process::exit(1);
+        }
+    };
     event_loop.run_app(&mut state).unwrap();
 }
+
+/// Reads the world seed from a settings file of `key = value` lines where
+/// lines starting with `#` are comments. A missing file sets no seed.
+fn read_seed_setting(path: &str) -> Result<Option<u64>, String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    };
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+            Some(("seed", value)) => {
+                return value.parse::<u64>().map(Some).map_err(|_| {
+                    format!("seed '{}' is not a whole positive number", value)
+                })
+            }
+            Some(_) => (),
+            None => return Err(format!("expected 'key = value', found '{}'", line)),
+        }
+    }
+    Ok(None)
+}
+
+/// Parses and validates the map data without opening a window.
+/// Prints all found problems and returns the process exit code.
+fn validate(path: &str) -> i32 {
+    let (tilemaps, textures, animations, _) = match map::parser::parse(path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        }
+    };
+    let issues = map::validation::validate(&tilemaps, &textures, &animations);
+    for issue in &issues {
+        eprintln!("{}", issue);
+    }
+    let warning_count = issues
+        .iter()
+        .filter(|issue| issue.severity() == Severity::Warning)
+        .count();
+    let mut error_count = issues.len() - warning_count;
+    let blueprint_count = tilemaps.len();
+    // Rooms around the start have to be generated for the game to start.
+    // Only the rooms of the seed 0 are generated, so the starting area of
+    // other seeds can still fail if some blueprint combinations don't fit.
+    if let Err(e) = map::Map::new(tilemaps, MapSettings::new(0)) {
+        eprintln!("error: {}", e);
+        error_count += 1;
+    }
+    println!(
+        "{} blueprints checked: {} errors, {} warnings",
+        blueprint_count,
+        error_count,
+        warning_count
+    );
+    if error_count > 0 {
+        1
+    } else {
+        0
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt;
+
+use rand::{distributions::WeightedIndex, prelude::Distribution, seq::SliceRandom, Rng};
+
+use super::{
+    portal::PortalID,
+    tilemap::{Tilemap, TilemapID},
+};
+
+/// Blueprint properties which decide where rooms of the blueprint can be generated.
+#[derive(Debug, Clone)]
+pub struct GenerationRules {
+    /// How likely it is for the blueprint to be chosen compared to others.
+    pub weight: f32,
+    /// How many rooms of the blueprint can be loaded at the same time.
+    /// Unloaded rooms don't count, so more can be generated over time.
+    /// No limit if `None`.
+    pub max_occurrences: Option<u32>,
+    /// Number of portals the player has to pass from the
+    /// starting room before the blueprint can appear.
+    pub min_depth: u32,
+    pub tags: Vec<String>,
+    /// Tags of which the neighbouring blueprint needs at least one.
+    /// Any blueprint can be a neighbour if empty.
+    pub neighbour_tags: Vec<String>,
+    /// Tags of each portal, in the same order as the portals of the blueprint.
+    /// Portals can be linked only if both have no tags or they share one.
+    pub portal_tags: Vec<Vec<String>>,
+}
+
+impl Default for GenerationRules {
+    fn default() -> Self {
+        Self {
+            weight: 1.0,
+            max_occurrences: None,
+            min_depth: 0,
+            tags: Vec::new(),
+            neighbour_tags: Vec::new(),
+            portal_tags: Vec::new(),
+        }
+    }
+}
+
+impl GenerationRules {
+    /// Whether one more room can be generated from the blueprint
+    /// while `occurrences` rooms of it are loaded.
+    pub(super) fn allows_occurrence(&self, occurrences: u32) -> bool {
+        self.max_occurrences.is_none_or(|max| occurrences < max)
+    }
+
+    fn accepts_neighbour(&self, neighbour: &GenerationRules) -> bool {
+        self.neighbour_tags.is_empty()
+            || shares_tag(&self.neighbour_tags, &neighbour.tags)
+    }
+
+    fn portal_tags(&self, portal: PortalID) -> &[String] {
+        self.portal_tags.get(portal.0).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Chooses the blueprint of the room behind the `portal` of the room made
+/// from the `source` blueprint and the portal of the new room to link with.
+/// The new room is `depth` portals away from the starting room and
+/// `occurrences` holds the number of already generated rooms of each blueprint.
+pub(super) fn choose_destination<R: Rng>(
+    rng: &mut R,
+    tilemaps: &[Tilemap],
+    occurrences: &[u32],
+    source: &Tilemap,
+    portal: PortalID,
+    depth: u32,
+) -> Result<(TilemapID, PortalID), GenerationError> {
+    let src_rules = &source.generation_rules;
+    let src_portal_tags = src_rules.portal_tags(portal);
+
+    // Blueprints which meet all the rules, with their portals which can be linked
+    let candidates: Vec<(&Tilemap, Vec<PortalID>)> = tilemaps
+        .iter()
+        .filter(|tilemap| {
+            let rules = &tilemap.generation_rules;
+            tilemap.repeatable
+                && rules.weight > 0.0
+                && rules.allows_occurrence(occurrences[tilemap.id.0])
+                && depth >= rules.min_depth
+                && src_rules.accepts_neighbour(rules)
+                && rules.accepts_neighbour(src_rules)
+        })
+        .map(|tilemap| {
+            let portals = tilemap
+                .unlinked_portals
+                .iter()
+                .map(|portal| portal.id)
+                .filter(|&id| {
+                    let tags = tilemap.generation_rules.portal_tags(id);
+                    (tags.is_empty() && src_portal_tags.is_empty())
+                        || shares_tag(tags, src_portal_tags)
+                })
+                .collect();
+            (tilemap, portals)
+        })
+        .filter(|(_, portals): &(_, Vec<PortalID>)| !portals.is_empty())
+        .collect();
+
+    // Weights are scaled down to at most 1, so even the biggest finite
+    // weights can't add up to infinity, which can't be sampled
+    let max_weight = candidates
+        .iter()
+        .map(|(tilemap, _)| tilemap.generation_rules.weight)
+        .fold(0.0, f32::max);
+    let weights = candidates
+        .iter()
+        .map(|(tilemap, _)| tilemap.generation_rules.weight / max_weight);
+    // Fails only if there are no candidates
+    let distribution = WeightedIndex::new(weights).map_err(|_| {
+        let position = source.unlinked_portals[portal.0].position;
+        GenerationError::NoMatchingBlueprint {
+            blueprint: source.name.clone(),
+            portal: (position.x, source.dimensions.1 - position.z - 1),
+            depth,
+        }
+    })?;
+    let (tilemap, portals) = &candidates[distribution.sample(rng)];
+    let dest_portal = *portals.choose(rng).unwrap();
+
+    Ok((tilemap.id, dest_portal))
+}
+
+fn shares_tag(a: &[String], b: &[String]) -> bool {
+    a.iter().any(|tag| b.contains(tag))
+}
+
+/// Error which stops the map from being generated any further.
+#[derive(Debug)]
+pub enum GenerationError {
+    MissingStartingBlueprint(&'static str),
+    /// No blueprint meets the rules for the room behind the portal.
+    NoMatchingBlueprint {
+        blueprint: String,
+        /// Coordinates of the portal tile, the same as shown in Tiled.
+        portal: (u64, u64),
+        depth: u32,
+    },
+}
+
+impl fmt::Display for GenerationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenerationError::MissingStartingBlueprint(name) => {
+                write!(f, "there is no starting blueprint named '{}'", name)
+            }
+            GenerationError::NoMatchingBlueprint {
+                blueprint,
+                portal,
+                depth,
+            } => write!(
+                f,
+                "no blueprint can be linked to the portal at ({}, {}) of blueprint '{}' at depth {}",
+                portal.0, portal.1, blueprint, depth
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GenerationError {}
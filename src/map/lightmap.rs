@@ -0,0 +1,281 @@
+use glam::Vec3;
+use tiled::Properties;
+
+use super::tilemap::Tile;
+
+/// Distance between two occlusion test points along a light ray.
+const OCCLUSION_STEP: f32 = 0.05;
+/// How far from the surface the light is sampled so the surface
+/// itself doesn't block the light.
+const SURFACE_OFFSET: f32 = 0.01;
+
+/// A light placed in a [`Tilemap`](super::tilemap::Tilemap) which is baked
+/// into its [`TileLight`]s when the map gets loaded.
+#[derive(Debug, Clone)]
+pub struct PointLight {
+    pub position: Vec3,
+    /// Linear RGB color with each channel in range [0.0, 1.0].
+    pub color: Vec3,
+    /// Distance in tiles at which the light completely fades out.
+    pub radius: f32,
+    pub intensity: f32,
+    /// Custom properties of the Tiled object or tile.
+    pub properties: Properties,
+}
+
+/// Baked light which falls onto each face of a tile.
+/// Walls are ordered by the direction they are facing: north (+z),
+/// east (+x), south (-z) and west (-x).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TileLight {
+    pub ground: Vec3,
+    pub ceiling: Vec3,
+    pub bottom_walls: [Vec3; 4],
+    pub top_walls: [Vec3; 4],
+    /// Light under the bottom and on the top of a stacked span, which
+    /// are seen from outside of it. Tiles of the first layer have none.
+    pub bottom: Vec3,
+    pub top: Vec3,
+}
+
+impl TileLight {
+    /// Returns the light on the bottom wall side with the provided normal.
+    #[inline]
+    pub fn bottom_wall(&self, normal: Vec3) -> Vec3 {
+        self.bottom_walls[side_index(normal)]
+    }
+
+    /// Returns the light on the top wall side with the provided normal.
+    #[inline]
+    pub fn top_wall(&self, normal: Vec3) -> Vec3 {
+        self.top_walls[side_index(normal)]
+    }
+}
+
+/// Tile wall normals in the same order as the walls in [`TileLight`].
+const SIDE_NORMALS: [Vec3; 4] = [Vec3::Z, Vec3::X, Vec3::NEG_Z, Vec3::NEG_X];
+
+#[inline]
+pub(super) fn side_index(normal: Vec3) -> usize {
+    if normal.z > 0.0 {
+        0
+    } else if normal.x > 0.0 {
+        1
+    } else if normal.z < 0.0 {
+        2
+    } else {
+        3
+    }
+}
+
+/// Calculates the light on every tile face from all provided lights.
+/// Light is blocked by every wall (bottom or top) which stands between
+/// the light and the face, including the walls of the stacked spans.
+pub fn bake(
+    tiles: &[Tile],
+    span_layers: &[Vec<Option<Tile>>],
+    dimensions: (u64, u64),
+    lights: &[PointLight],
+) -> Vec<TileLight> {
+    let grid = TileGrid {
+        tiles,
+        span_layers,
+        dimensions,
+    };
+    if lights.is_empty() {
+        return vec![TileLight::default(); tiles.len()];
+    }
+    tiles
+        .iter()
+        .map(|tile| grid.tile_light(tile, |index| Some(&tiles[index]), lights))
+        .collect()
+}
+
+/// Calculates the light on every face of the spans of each span layer,
+/// like [`bake`] does for the tiles. Empty places get no light.
+pub fn bake_spans(
+    tiles: &[Tile],
+    span_layers: &[Vec<Option<Tile>>],
+    dimensions: (u64, u64),
+    lights: &[PointLight],
+) -> Vec<Vec<TileLight>> {
+    let grid = TileGrid {
+        tiles,
+        span_layers,
+        dimensions,
+    };
+    span_layers
+        .iter()
+        .map(|spans| {
+            spans
+                .iter()
+                .map(|span| match span {
+                    Some(span) if !lights.is_empty() => {
+                        let mut light =
+                            grid.tile_light(span, |index| spans[index].as_ref(), lights);
+                        let center = Vec3::new(
+                            span.position.x as f32 + 0.5,
+                            0.0,
+                            span.position.z as f32 + 0.5,
+                        );
+                        let bottom = Vec3::new(center.x, span.bottom_height, center.z);
+                        light.bottom = grid.light_at(bottom, Vec3::NEG_Y, lights);
+                        let top = Vec3::new(center.x, span.top_height, center.z);
+                        light.top = grid.light_at(top, Vec3::Y, lights);
+                        light
+                    }
+                    _ => TileLight::default(),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+struct TileGrid<'a> {
+    tiles: &'a [Tile],
+    span_layers: &'a [Vec<Option<Tile>>],
+    dimensions: (u64, u64),
+}
+
+impl<'a> TileGrid<'a> {
+    fn index(&self, x: i64, z: i64) -> Option<usize> {
+        if x < 0
+            || z < 0
+            || x >= self.dimensions.0 as i64
+            || z >= self.dimensions.1 as i64
+        {
+            return None;
+        }
+        Some(z as usize * self.dimensions.0 as usize + x as usize)
+    }
+
+    /// Calculates the light on the platforms and the walls of the tile, or of
+    /// the span, of which the neighbours of the same layer are found by index.
+    fn tile_light<'t, F>(
+        &self,
+        tile: &Tile,
+        neighbour_at: F,
+        lights: &[PointLight],
+    ) -> TileLight
+    where
+        F: Fn(usize) -> Option<&'t Tile>,
+    {
+        let mut tile_light = TileLight::default();
+        let center = Vec3::new(
+            tile.position.x as f32 + 0.5,
+            0.0,
+            tile.position.z as f32 + 0.5,
+        );
+        // Platforms are only visible if there is space between them
+        let ground_height = tile.ground_height_at(center.x, center.z);
+        let ceiling_height = tile.ceiling_height_at(center.x, center.z);
+        if ground_height < ceiling_height {
+            let ground = Vec3::new(center.x, ground_height, center.z);
+            let normal = tile.ground_slope.floor_normal();
+            tile_light.ground = self.light_at(ground, normal, lights);
+            let ceiling = Vec3::new(center.x, ceiling_height, center.z);
+            let normal = -tile.ceiling_slope.floor_normal();
+            tile_light.ceiling = self.light_at(ceiling, normal, lights);
+        }
+
+        for (side, normal) in SIDE_NORMALS.into_iter().enumerate() {
+            let Some(index) = self.index(
+                tile.position.x as i64 + normal.x as i64,
+                tile.position.z as i64 + normal.z as i64,
+            ) else {
+                continue;
+            };
+            let edge = center + normal * 0.5;
+            // Sloped platforms are sampled in the middle of the edge.
+            // Nothing covers the walls of a span without a neighbouring span.
+            let (neighbour_ground, neighbour_ceiling) = match neighbour_at(index) {
+                Some(neighbour) => (
+                    neighbour.ground_height_at(edge.x, edge.z),
+                    neighbour.ceiling_height_at(edge.x, edge.z),
+                ),
+                None => (f32::NEG_INFINITY, f32::INFINITY),
+            };
+
+            // Only the part of the wall which isn't covered by
+            // the neighbouring tile can be seen and lit
+            let bottom = tile.bottom_height.max(neighbour_ground);
+            let top = tile.ground_height_at(edge.x, edge.z).min(neighbour_ceiling);
+            if bottom < top {
+                let point = Vec3::new(edge.x, (bottom + top) * 0.5, edge.z);
+                tile_light.bottom_walls[side] = self.light_at(point, normal, lights);
+            }
+            let bottom = tile.ceiling_height_at(edge.x, edge.z).max(neighbour_ground);
+            let top = tile.top_height.min(neighbour_ceiling);
+            if bottom < top {
+                let point = Vec3::new(edge.x, (bottom + top) * 0.5, edge.z);
+                tile_light.top_walls[side] = self.light_at(point, normal, lights);
+            }
+        }
+        tile_light
+    }
+
+    /// Returns `true` if the point is inside of a wall of a tile
+    /// or of a span, or if it is outside of the map.
+    fn is_solid(&self, point: Vec3) -> bool {
+        let Some(index) = self.index(point.x.floor() as i64, point.z.floor() as i64)
+        else {
+            return true;
+        };
+        let is_inside = |tile: &Tile| {
+            (tile.bottom_height < point.y
+                && point.y < tile.ground_height_at(point.x, point.z))
+                || (tile.ceiling_height_at(point.x, point.z) < point.y
+                    && point.y < tile.top_height)
+        };
+        is_inside(&self.tiles[index])
+            || self
+                .span_layers
+                .iter()
+                .filter_map(|spans| spans[index].as_ref())
+                .any(is_inside)
+    }
+
+    fn light_at(&self, point: Vec3, normal: Vec3, lights: &[PointLight]) -> Vec3 {
+        let point = point + normal * SURFACE_OFFSET;
+        lights
+            .iter()
+            .filter_map(|light| {
+                let to_light = light.position - point;
+                let distance = to_light.length();
+                if distance >= light.radius {
+                    return None;
+                }
+                let diffuse = (to_light / distance).dot(normal);
+                if diffuse <= 0.0 || self.is_occluded(point, light.position) {
+                    return None;
+                }
+                let falloff = 1.0 - distance / light.radius;
+                Some(light.color * light.intensity * diffuse * falloff * falloff)
+            })
+            .sum()
+    }
+
+    fn is_occluded(&self, from: Vec3, to: Vec3) -> bool {
+        let steps = (from.distance(to) / OCCLUSION_STEP) as u32;
+        (1..steps).any(|step| self.is_solid(from.lerp(to, step as f32 / steps as f32)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::parser::parse_test_blueprint;
+
+    #[test]
+    fn spans_block_the_light_from_above() {
+        // Light above the middle of a bridge over `x` = 3 and 4
+        let tilemap = parse_test_blueprint("bridge");
+        let index = |x: usize, z: usize| z * tilemap.dimensions.0 as usize + x;
+
+        assert_eq!(tilemap.lightmap[index(3, 1)].ground, Vec3::ZERO);
+        assert_eq!(tilemap.lightmap[index(4, 1)].ground, Vec3::ZERO);
+        assert!(tilemap.span_lightmaps[0][index(3, 1)].ground.x > 0.0);
+        // The light passes by the bridge to the ground further away
+        assert!(tilemap.lightmap[index(0, 1)].ground.x > 0.0);
+    }
+}
@@ -1,90 +1,480 @@
 // TODO check if these really need to be pub
 pub mod tilemap;
+pub mod generator;
+pub mod lightmap;
+pub mod mover;
 pub mod parser;
 pub mod portal;
 pub mod room;
+pub mod validation;
 
-use std::path::PathBuf;
+use std::{collections::VecDeque, path::PathBuf, sync::Arc};
 
-use glam::Vec2;
-use tilemap::Tilemap;
-use portal::{Orientation, Rotation};
-use rand::seq::SliceRandom;
-use room::{Room, RoomID, RoomRef};
+use glam::{Mat2, Vec2, Vec3};
+use tilemap::{Spawn, TilePlatforms, Tilemap};
+use portal::PortalTransform;
+use generator::GenerationError;
+use hashbrown::HashMap;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use room::{Room, RoomID, RoomOrigin, RoomRef};
 
 const DEFAULT_ROOM_DIRECTION: Vec2 = Vec2::Y;
+/// Name of the blueprint in which the player starts.
+const STARTING_BLUEPRINT: &str = "start";
+const DEFAULT_UNLOAD_DISTANCE: u32 = 6;
+/// Where the player starts if the starting blueprint has no spawn.
+const DEFAULT_SPAWN: Spawn = Spawn {
+    position: Vec3::new(5.5, 2.0, 4.5),
+    yaw: std::f32::consts::FRAC_PI_2,
+};
+
+/// Settings of the world which stay the same while playing.
+#[derive(Debug, Clone, Copy)]
+pub struct MapSettings {
+    /// Seed from which all rooms are generated.
+    pub seed: u64,
+    /// Rooms more than this many portals away from the player get unloaded.
+    pub unload_distance: u32,
+    /// What happens to the portals which lead into unloaded rooms.
+    pub unloaded_links: UnloadedLinks,
+}
+
+impl MapSettings {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            unload_distance: DEFAULT_UNLOAD_DISTANCE,
+            unloaded_links: UnloadedLinks::Regenerate,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnloadedLinks {
+    /// The same room is generated again behind the portal.
+    Regenerate,
+    /// The portal forgets the room and a new one is generated behind it.
+    Forget,
+}
 
 pub struct Map {
     tilemaps: Vec<Tilemap>,
-    rooms: Vec<Room>,
+    /// Currently loaded rooms.
+    rooms: HashMap<RoomID, Room>,
+    next_room_id: usize,
+    /// Number of loaded rooms of each blueprint.
+    occurrences: Vec<u32>,
+    settings: MapSettings,
 }
 
 impl Map {
-    // TODO starting blueprint is always '0' and main room is '1'
-    pub fn new(tilemaps: Vec<Tilemap>) -> Self {
-        let mut rooms = Vec::new();
-        let mut room_counter = 0;
-        let mut rng = rand::thread_rng();
-
-        // Select the first blueprint which repeats only once
-        let tilemap = &tilemaps[0];
-        let mut starting_room = Room {
-            id: RoomID(room_counter),
-            tilemap_id: tilemap.id,
-            portals: tilemap.unlinked_portals.clone(),
-            is_fully_generated: false,
-            skybox: tilemap.default_skybox,
-            ambient_light_intensity: tilemap.default_ambient_light,
-            direction: DEFAULT_ROOM_DIRECTION,
+    /// Creates the starting room with all of its adjacent rooms.
+    /// Further rooms are generated as the player explores.
+    /// The same seed and blueprints always generate the same rooms behind
+    /// the same portals, as long as the player explores in the same order
+    /// and the same rooms are seen, since blueprint occurrence limits count
+    /// the loaded rooms. Without the limits the order doesn't matter.
+    pub fn new(tilemaps: Vec<Tilemap>, settings: MapSettings) -> Result<Self, GenerationError> {
+        let starting_tilemap = tilemaps
+            .iter()
+            .find(|tilemap| tilemap.name == STARTING_BLUEPRINT)
+            .ok_or(GenerationError::MissingStartingBlueprint(STARTING_BLUEPRINT))?;
+        let starting_room = Room::new(
+            RoomID(0),
+            starting_tilemap,
+            DEFAULT_ROOM_DIRECTION,
+            settings.seed,
+            0,
+            None,
+        );
+        let mut occurrences = vec![0; tilemaps.len()];
+        occurrences[starting_tilemap.id.0] += 1;
+
+        let mut map = Self {
+            tilemaps,
+            rooms: HashMap::from([(RoomID(0), starting_room)]),
+            next_room_id: 1,
+            occurrences,
+            settings,
         };
-        room_counter += 1;
+        map.generate_adjacent_rooms(RoomID(0))?;
+        Ok(map)
+    }
 
-        let root_segment = &tilemaps[1];
-        let mut adjacent_rooms: Vec<Room> = starting_room
+    /// Makes sure the current room and all rooms next to it lead somewhere,
+    /// so the player can never walk or look into an unlinked portal nearby.
+    /// Rooms too far away from the current room get unloaded, unless they
+    /// are still seen, so the rooms in view never change.
+    pub fn update(
+        &mut self,
+        current_room: RoomID,
+        seen_rooms: &[RoomID],
+    ) -> Result<(), GenerationError> {
+        self.generate_within_distance(current_room, 0)?;
+        let adjacent_rooms: Vec<RoomID> = self.rooms[&current_room]
             .portals
-            .iter_mut()
-            .map(|portal| {
-                let mut new_room = Room {
-                    id: RoomID(room_counter),
-                    tilemap_id: root_segment.id,
-                    portals: root_segment.unlinked_portals.clone(),
-                    //objects: blueprint.object_placeholders.clone(),
-                    is_fully_generated: false,
-                    skybox: root_segment.default_skybox,
-                    ambient_light_intensity: root_segment.default_ambient_light,
-
-                    // Temporary value
-                    direction: Vec2::ZERO
-                };
-                let dest_portal = new_room.portals.choose_mut(&mut rng).unwrap();
-                let src_angle = f32::atan2(portal.direction.y, portal.direction.x);
-                let dest_angle = f32::atan2(-dest_portal.direction.y, -dest_portal.direction.x);
-                // Angle for how much to rotate the destination room
-                let diff = dest_angle - src_angle;
-                let rotation = glam::mat2(Vec2::new(diff.cos(), diff.sin()), Vec2::new(-diff.sin(), diff.cos()));
-                let dest_room_direction = rotation * DEFAULT_ROOM_DIRECTION;
-                new_room.direction = dest_room_direction;
-                // Connect the two portals:
-                // Connect the starting room with new random room
-                portal.destination = Some((new_room.id, dest_portal.id));
-                // Connect the new random room with the starting room
-                dest_portal.destination = Some((starting_room.id, portal.id));
-                room_counter += 1;
-
-                new_room
-            })
+            .iter()
+            .filter_map(|portal| portal.destination.map(|(room_id, _)| room_id))
+            .collect();
+        for room_id in adjacent_rooms {
+            self.generate_within_distance(room_id, 1)?;
+        }
+        self.unload_distant_rooms(current_room, seen_rooms);
+        Ok(())
+    }
+
+    /// Links the unlinked portals of the rooms which were seen through other
+    /// portals, as long as the new rooms are close enough to the current room
+    /// to stay loaded. Rooms which are not loaded anymore are skipped.
+    /// Which rooms the renderer sees changes the loaded rooms, and with
+    /// occurrence limits also the rooms generated behind other portals.
+    pub fn generate_seen_rooms(
+        &mut self,
+        current_room: RoomID,
+        seen_rooms: &[RoomID],
+    ) -> Result<(), GenerationError> {
+        let distances = self.room_distances(current_room);
+        for room_id in seen_rooms {
+            if let Some(&distance) = distances.get(room_id) {
+                self.generate_within_distance(*room_id, distance)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Generates the rooms behind the portals of the room which is `distance`
+    /// portals away from the current room, unless they would be unloaded
+    /// right after, which would generate them again every frame.
+    fn generate_within_distance(
+        &mut self,
+        room_id: RoomID,
+        distance: u32,
+    ) -> Result<(), GenerationError> {
+        if distance < self.settings.unload_distance {
+            self.generate_adjacent_rooms(room_id)?;
+        }
+        Ok(())
+    }
+
+    /// Links every unlinked portal of the room with a new room generated
+    /// from a repeatable blueprint which meets the generation rules.
+    /// Portals which lead into unloaded rooms get them back, or a new room
+    /// if they were forgotten. Nothing happens if the room is not loaded.
+    fn generate_adjacent_rooms(
+        &mut self,
+        room_id: RoomID,
+    ) -> Result<(), GenerationError> {
+        let Self {
+            tilemaps,
+            rooms,
+            next_room_id,
+            occurrences,
+            ..
+        } = self;
+        let Some(current_room) = rooms.get(&room_id) else {
+            return Ok(());
+        };
+        if current_room.is_fully_generated {
+            return Ok(());
+        }
+
+        // Each room has its own generator, so the rooms behind its portals
+        // don't depend on which other rooms were generated before
+        let mut rng = ChaCha8Rng::seed_from_u64(current_room.seed);
+        let mut new_rooms = Vec::new();
+        for (portal, unloaded_link) in current_room
+            .portals
+            .iter()
+            .zip(&current_room.unloaded_links)
+        {
+            // Values are drawn even for the linked portals so each
+            // portal always gets the same values
+            let room_seed: u64 = rng.gen();
+            let choice_seed: u64 = rng.gen();
+            if portal.destination.is_some() {
+                continue;
+            }
+
+            // Unloaded rooms come back unless they were forgotten
+            let origin = current_room
+                .origin
+                .as_ref()
+                .filter(|origin| origin.portal_id.0 == portal.id.0)
+                .filter(|_| unloaded_link.forgotten_count == 0);
+            // A room which came back can't go over the limit of its blueprint
+            let stub = unloaded_link
+                .stub
+                .clone()
+                .or_else(|| origin.map(|origin| origin.room.clone()))
+                .filter(|stub| {
+                    tilemaps[stub.tilemap_id.0]
+                        .generation_rules
+                        .allows_occurrence(occurrences[stub.tilemap_id.0])
+                });
+            let (tilemap_id, dest_portal_id, room_seed, depth, new_origin) = match stub {
+                Some(stub) => (stub.tilemap_id, stub.portal_id, stub.seed, stub.depth, stub.origin),
+                None => {
+                    // Forgotten rooms are chosen from a different stream of values
+                    let mut choice_rng = ChaCha8Rng::seed_from_u64(choice_seed);
+                    choice_rng.set_stream(unloaded_link.forgotten_count);
+                    let room_seed = if unloaded_link.forgotten_count == 0 {
+                        room_seed
+                    } else {
+                        choice_rng.gen()
+                    };
+                    let depth = current_room.depth + 1;
+                    let (tilemap_id, dest_portal_id) = generator::choose_destination(
+                        &mut choice_rng,
+                        tilemaps,
+                        occurrences,
+                        &tilemaps[current_room.tilemap_id.0],
+                        portal.id,
+                        depth,
+                    )?;
+                    let new_origin = RoomOrigin {
+                        portal_id: dest_portal_id,
+                        room: current_room.to_stub(portal.id),
+                    };
+                    (tilemap_id, dest_portal_id, room_seed, depth, Some(Arc::new(new_origin)))
+                }
+            };
+            occurrences[tilemap_id.0] += 1;
+
+            let new_id = RoomID(*next_room_id);
+            *next_room_id += 1;
+            let mut new_room = Room::new(
+                new_id,
+                &tilemaps[tilemap_id.0],
+                Vec2::ZERO,
+                room_seed,
+                depth,
+                new_origin,
+            );
+            let dest_portal = &mut new_room.portals[dest_portal_id.0];
+            // Angle for how much to rotate the destination room
+            let yaw = PortalTransform::new(portal, dest_portal).yaw();
+            new_room.direction = Mat2::from_angle(yaw) * current_room.direction;
+            // Connect the new random room with the current room
+            dest_portal.destination = Some((room_id, portal.id));
+
+            new_rooms.push((portal.id, new_room));
+        }
+
+        let current_room = rooms.get_mut(&room_id).unwrap();
+        for (portal_id, new_room) in &new_rooms {
+            // Connect the current room with the new random room
+            let dest_portal = new_room
+                .portals
+                .iter()
+                .find(|portal| portal.destination.is_some())
+                .unwrap();
+            current_room.portals[portal_id.0].destination = Some((new_room.id, dest_portal.id));
+            current_room.unloaded_links[portal_id.0].stub = None;
+        }
+        current_room.is_fully_generated = true;
+        rooms.extend(new_rooms.into_iter().map(|(_, room)| (room.id, room)));
+        Ok(())
+    }
+
+    /// Returns how many portals away from the current room each room is,
+    /// for all rooms which are not further than the unload distance.
+    fn room_distances(&self, current_room: RoomID) -> HashMap<RoomID, u32> {
+        // Breadth-first search finds the shortest way to each room
+        let mut distances: HashMap<RoomID, u32> = HashMap::from([(current_room, 0)]);
+        let mut queue = VecDeque::from([current_room]);
+        while let Some(room_id) = queue.pop_front() {
+            let distance = distances[&room_id];
+            if distance == self.settings.unload_distance {
+                continue;
+            }
+            for portal in &self.rooms[&room_id].portals {
+                if let Some((next_id, _)) = portal.destination {
+                    if !distances.contains_key(&next_id) {
+                        distances.insert(next_id, distance + 1);
+                        queue.push_back(next_id);
+                    }
+                }
+            }
+        }
+        distances
+    }
+
+    /// Unloads all rooms which are more than the unload distance away from the
+    /// current room and not seen. Loaded rooms keep a stub or forget their
+    /// unloaded neighbours.
+    fn unload_distant_rooms(&mut self, current_room: RoomID, seen_rooms: &[RoomID]) {
+        let kept = self.room_distances(current_room);
+        if kept.len() == self.rooms.len() {
+            return;
+        }
+
+        let unloaded_ids: Vec<RoomID> = self
+            .rooms
+            .keys()
+            .filter(|&id| !kept.contains_key(id) && !seen_rooms.contains(id))
+            .copied()
             .collect();
-        rooms.push(starting_room);
-        rooms.append(&mut adjacent_rooms);
+        for id in &unloaded_ids {
+            let room = self.rooms.remove(id).unwrap();
+            self.occurrences[room.tilemap_id.0] -= 1;
+            for portal in &room.portals {
+                let Some((neighbour_id, neighbour_portal_id)) = portal.destination else {
+                    continue;
+                };
+                // Links between two unloaded rooms are not needed anymore
+                let Some(neighbour) = self.rooms.get_mut(&neighbour_id) else {
+                    continue;
+                };
+                neighbour.portals[neighbour_portal_id.0].destination = None;
+                neighbour.is_fully_generated = false;
+                let unloaded_link = &mut neighbour.unloaded_links[neighbour_portal_id.0];
+                match self.settings.unloaded_links {
+                    UnloadedLinks::Regenerate => unloaded_link.stub = Some(room.to_stub(portal.id)),
+                    UnloadedLinks::Forget => unloaded_link.forgotten_count += 1,
+                }
+            }
+        }
+    }
+
+    /// Returns the platforms of the tile at the coordinates of the loaded room,
+    /// like the renderer shows them. Linked portal tiles are replaced with the
+    /// tile right behind the destination portal, with heights converted into
+    /// the units of this room. Of the spans stacked on the tile, returns the
+    /// one in which the `y` level is.
+    pub fn get_tile_through_portals(
+        &self,
+        room_id: RoomID,
+        x: i64,
+        z: i64,
+        y: f32,
+    ) -> Option<TilePlatforms> {
+        let room = self.get_room_data(room_id);
+        let tile = room.get_span_checked(x, z, y)?;
+        let Some(src_portal) = tile.portal_id.map(|id| room.get_portal(id)) else {
+            return Some(tile.platforms());
+        };
+        let Some((dest_room_id, dest_portal_id)) = src_portal.destination else {
+            return Some(tile.platforms());
+        };
+        let dest_room = self.get_room_data(dest_room_id);
+        let dest_portal = dest_room.get_portal(dest_portal_id);
+
+        let center = Vec3::new(x as f32 + 0.5, src_portal.ground_height, z as f32 + 0.5);
+        let transform = PortalTransform::new(&src_portal, &dest_portal);
+        let behind = transform.position(center);
+        let dest_tile = dest_room.get_span_checked(
+            behind.x.floor() as i64,
+            behind.z.floor() as i64,
+            transform.height(y),
+        )?;
+        let back = PortalTransform::new(&dest_portal, &src_portal);
+        Some(dest_tile.platforms().through_portal(&back, tile.position))
+    }
+
+    /// Moves the movers of all loaded rooms for the passed time.
+    /// Only the player in its current room can trigger the movers.
+    pub fn update_movers(
+        &mut self,
+        delta: f32,
+        player_room: RoomID,
+        player_position: Vec3,
+    ) {
+        for (id, room) in self.rooms.iter_mut() {
+            let player_position = (*id == player_room).then_some(player_position);
+            let tilemap = &self.tilemaps[room.tilemap_id.0];
+            room.update_movers(tilemap, delta, player_position);
+        }
+    }
+
+    /// Returns where the player starts in the starting room.
+    pub fn spawn(&self) -> Spawn {
+        self.tilemaps
+            .iter()
+            .find(|tilemap| tilemap.name == STARTING_BLUEPRINT)
+            .and_then(|tilemap| tilemap.spawn)
+            .unwrap_or(DEFAULT_SPAWN)
+    }
 
-        Self { tilemaps, rooms }
+    pub fn settings(&self) -> MapSettings {
+        self.settings
     }
 
-    pub fn get_room_data(&self, index: RoomID) -> RoomRef {
-        let room = &self.rooms[index.0];
+    pub fn collect_dbg_data(&self) -> MapDebugData {
+        MapDebugData {
+            room_count: self.rooms.len() as u64,
+            seed: self.settings.seed,
+        }
+    }
+
+    /// Returns the data of the loaded room.
+    /// Rooms can't be used anymore once they are unloaded.
+    pub fn get_room_data(&self, index: RoomID) -> RoomRef<'_> {
+        let room = &self.rooms[&index];
         RoomRef {
             tilemap: &self.tilemaps[room.tilemap_id.0],
             data: room,
         }
     }
 }
+
+#[derive(Debug)]
+pub struct MapDebugData {
+    /// Number of loaded rooms.
+    pub room_count: u64,
+    pub seed: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ID, blueprint, seed and portal destinations of a room.
+    type RoomLayout = (usize, usize, u64, Vec<Option<(usize, usize)>>);
+
+    /// Layout of each loaded room, ordered by ID.
+    fn room_layout(map: &Map) -> Vec<RoomLayout> {
+        let mut layout: Vec<_> = map
+            .rooms
+            .values()
+            .map(|room| {
+                let destinations = room
+                    .portals
+                    .iter()
+                    .map(|portal| {
+                        portal.destination.map(|(room, portal)| (room.0, portal.0))
+                    })
+                    .collect();
+                (room.id.0, room.tilemap_id.0, room.seed, destinations)
+            })
+            .collect();
+        layout.sort_by_key(|(id, ..)| *id);
+        layout
+    }
+
+    /// Walks through the last portal of each room, seeing the rooms next to it.
+    fn explore(seed: u64) -> Map {
+        let (tilemaps, ..) = parser::parse("tiled").unwrap();
+        let mut map = Map::new(tilemaps, MapSettings::new(seed)).unwrap();
+        let mut current_room = RoomID(0);
+        for _ in 0..8 {
+            let seen_rooms: Vec<RoomID> = map.rooms[&current_room]
+                .portals
+                .iter()
+                .filter_map(|portal| portal.destination.map(|(room_id, _)| room_id))
+                .collect();
+            map.generate_seen_rooms(current_room, &seen_rooms).unwrap();
+            map.update(current_room, &seen_rooms).unwrap();
+            let portal = map.rooms[&current_room].portals.last().unwrap();
+            current_room = portal.destination.unwrap().0;
+        }
+        map
+    }
+
+    #[test]
+    fn same_seed_and_exploration_generate_same_rooms() {
+        let layout = room_layout(&explore(7));
+        assert!(layout.len() > 1);
+        assert_eq!(layout, room_layout(&explore(7)));
+    }
+}
@@ -0,0 +1,136 @@
+use super::tilemap::Tile;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MoverID(pub usize);
+
+/// Group of tiles with heights which move together between the heights from
+/// the blueprint and the open heights, like doors, elevators and crushers.
+#[derive(Debug, Clone)]
+pub struct Mover {
+    /// Name by which the trigger tiles find the mover.
+    pub name: String,
+    pub tiles: Vec<MovedTile>,
+    /// Seconds it takes to fully open or close.
+    pub travel_time: f32,
+    /// Seconds the mover stays open, and closed if it opens by itself.
+    pub wait_time: f32,
+    pub activation: Activation,
+}
+
+/// Tile moved by a [`Mover`] and how far its heights move when fully open.
+#[derive(Debug, Clone, Copy)]
+pub struct MovedTile {
+    /// Index of the tile in the tiles of the blueprint.
+    pub index: usize,
+    pub ground_offset: f32,
+    pub ceiling_offset: f32,
+    pub top_offset: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activation {
+    /// Opens while the player stands on one of its trigger tiles
+    /// and closes again after the wait time.
+    Trigger,
+    /// Opens and closes by itself, waiting the wait time in between.
+    Timer,
+}
+
+impl Mover {
+    /// Moves the heights of the room tiles to the `progress` of opening, in
+    /// range [0.0, 1.0]. Heights of the blueprint tiles are the closed heights.
+    pub fn apply(&self, progress: f32, blueprint: &[Tile], tiles: &mut [Tile]) {
+        for moved in &self.tiles {
+            let closed = &blueprint[moved.index];
+            let tile = &mut tiles[moved.index];
+            tile.ground_height = closed.ground_height + moved.ground_offset * progress;
+            tile.ceiling_height = closed.ceiling_height + moved.ceiling_offset * progress;
+            tile.top_height = closed.top_height + moved.top_offset * progress;
+        }
+    }
+}
+
+/// Position of a [`Mover`] in a room. Every mover starts closed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MoverState {
+    /// How far the mover is open, in range [0.0, 1.0].
+    pub progress: f32,
+    is_opening: bool,
+    /// Seconds left to wait before moving again.
+    wait_left: f32,
+}
+
+impl MoverState {
+    /// Moves the mover for the passed time. Triggered movers keep
+    /// waiting open for as long as they are triggered.
+    pub fn update(&mut self, mover: &Mover, delta: f32, is_triggered: bool) {
+        if is_triggered && mover.activation == Activation::Trigger {
+            self.is_opening = true;
+            self.wait_left = mover.wait_time;
+        }
+        let target = if self.is_opening { 1.0 } else { 0.0 };
+        if self.progress != target {
+            let step = delta / mover.travel_time.max(f32::EPSILON);
+            self.progress = if self.is_opening {
+                (self.progress + step).min(1.0)
+            } else {
+                (self.progress - step).max(0.0)
+            };
+            return;
+        }
+        // Triggered movers wait only while they are open
+        if mover.activation == Activation::Trigger && !self.is_opening {
+            return;
+        }
+        self.wait_left -= delta;
+        if self.wait_left <= 0.0 {
+            self.is_opening = !self.is_opening;
+            self.wait_left = mover.wait_time;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn movers_open_and_close() {
+        let mut mover = Mover {
+            name: "door".to_owned(),
+            tiles: Vec::new(),
+            travel_time: 1.0,
+            wait_time: 2.0,
+            activation: Activation::Trigger,
+        };
+        let mut state = MoverState::default();
+        let run = |state: &mut MoverState, mover: &Mover, seconds: f32, is_triggered| {
+            for _ in 0..(seconds * 100.0).round() as u32 {
+                state.update(mover, 0.01, is_triggered);
+            }
+        };
+
+        // Stays closed until triggered, then waits open while triggered
+        run(&mut state, &mover, 5.0, false);
+        assert_eq!(state.progress, 0.0);
+        run(&mut state, &mover, 0.5, true);
+        assert!((state.progress - 0.5).abs() < 0.02);
+        run(&mut state, &mover, 5.0, true);
+        assert_eq!(state.progress, 1.0);
+        // Closes after the wait time
+        run(&mut state, &mover, 1.9, false);
+        assert_eq!(state.progress, 1.0);
+        run(&mut state, &mover, 1.2, false);
+        assert_eq!(state.progress, 0.0);
+
+        // Timers open and close by themselves
+        mover.activation = Activation::Timer;
+        let mut state = MoverState::default();
+        run(&mut state, &mover, 0.5, false);
+        assert!((state.progress - 0.5).abs() < 0.02);
+        run(&mut state, &mover, 2.0, false);
+        assert_eq!(state.progress, 1.0);
+        run(&mut state, &mover, 2.0, false);
+        assert_eq!(state.progress, 0.0);
+    }
+}
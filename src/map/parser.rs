@@ -1,163 +1,1255 @@
 use std::{
-    fs,
-    path::{Path},
+    fmt, fs, io,
+    path::{Path, PathBuf},
 };
 
-use glam::Vec2;
+use glam::{Vec2, Vec3};
 use image::{EncodableLayout, ImageReader};
-use tiled::{Loader, PropertyValue, TileLayer};
+use tiled::{Loader, ObjectShape, Properties, PropertyValue, TileLayer};
 
 use crate::{
+    models::{ModelData, ModelID, VoxScene, VOXEL_HEIGHT_SCALE},
     raycaster::PointXZ,
-    textures::{ TextureData, TextureID},
+    textures::{TextureAnimation, TextureData, TextureID, Transparency},
 };
 
 use super::{
-    portal::{ Orientation, Portal, PortalID}, tilemap::{Skybox, Tile, Tilemap, TilemapID}
+    generator::GenerationRules,
+    lightmap::{self, PointLight},
+    mover::{Activation, MovedTile, Mover, MoverID},
+    portal::{Portal, PortalID},
+    tilemap::{
+        Fog, Object, ObjectID, Skybox, Slope, Spawn, Spawner, Sprite, TextureMapping,
+        Tile, Tilemap, TilemapID, Trigger,
+    },
 };
 
-pub fn parse<P: AsRef<Path>>(path: P) -> (Vec<Tilemap>, Vec<TextureData>) {
+const DEFAULT_LIGHT_COLOR: Vec3 = Vec3::ONE;
+const DEFAULT_LIGHT_RADIUS: f32 = 5.0;
+/// Default height of a light above the tile ground
+const DEFAULT_LIGHT_HEIGHT: f32 = 0.5;
+const DEFAULT_LIGHT_INTENSITY: f32 = 1.0;
+// Classes of the objects in the object layers
+const SPRITE_CLASS: &str = "sprite";
+const SPAWN_CLASS: &str = "spawn";
+const TRIGGER_CLASS: &str = "trigger";
+const SPAWNER_CLASS: &str = "spawner";
+const LIGHT_CLASS: &str = "light";
+const DEFAULT_SPRITE_SIZE: f32 = 1.0;
+/// Default seconds it takes a mover to fully open or close
+const DEFAULT_MOVER_TRAVEL_TIME: f32 = 1.0;
+/// Default seconds a mover stays open
+const DEFAULT_MOVER_WAIT_TIME: f32 = 3.0;
+
+/// Blueprints, textures, animated textures and models of the parsed map data.
+pub type ParsedMap = (
+    Vec<Tilemap>,
+    Vec<TextureData>,
+    Vec<TextureAnimation>,
+    Vec<ModelData>,
+);
+type NamedTextures = [(String, TextureData)];
+/// Vox scenes by their file names, with the ID of the first model of each scene,
+/// since the models of all scenes are stored together.
+type NamedModels = [(String, ModelID, VoxScene)];
+
+/// Textures found by their file names and the animated textures made
+/// from them while parsing, which take the IDs after the textures.
+struct TextureSet<'a> {
+    named: &'a NamedTextures,
+    animations: Vec<TextureAnimation>,
+}
+
+impl TextureSet<'_> {
+    /// Returns the ID of the animation, which all the same animations share.
+    fn animation_id(&mut self, animation: TextureAnimation) -> TextureID {
+        let index = match self.animations.iter().position(|other| *other == animation) {
+            Some(index) => index,
+            None => {
+                self.animations.push(animation);
+                self.animations.len() - 1
+            }
+        };
+        TextureID(self.named.len() + 1 + index)
+    }
+}
+
+pub fn parse<P: AsRef<Path>>(path: P) -> Result<ParsedMap, ParseError> {
     let texture_dir_path = path.as_ref().join("textures");
-    let texture_dir =
-        fs::read_dir(texture_dir_path).expect("Couldn't find 'texture' dir");
-    let texture_array: Vec<(String, TextureData)> = texture_dir
-        .flatten()
-        .enumerate()
-        .filter(|(_, texture)| texture.metadata().unwrap().is_file())
-        .map(|(i, texture)| {
-            let texture_name = texture.file_name().to_str().unwrap().to_owned();
-            println!("name: {}", texture_name);
-            let data = ImageReader::open(texture.path()).unwrap().decode().unwrap();
-            (texture_name, TextureData::new(
-                TextureID(i),
-                data.to_rgba8().as_bytes().to_vec(),
-                data.width() as usize,
-                data.height() as usize,
-                false,
+    let texture_array: Vec<(String, TextureData)> = read_dir_sorted(&texture_dir_path)?
+        .into_iter()
+        .filter(|path| path.is_file())
+        .map(|texture_path| {
+            let texture_name = file_name(&texture_path);
+            let data = ImageReader::open(&texture_path)
+                .map_err(|e| ParseError::in_file(&texture_path, ParseErrorKind::Io(e)))?
+                .decode()
+                .map_err(|e| {
+                    ParseError::in_file(&texture_path, ParseErrorKind::Image(e))
+                })?;
+            let rgba = data.to_rgba8().as_bytes().to_vec();
+            let transparency = Transparency::from_rgba(&rgba);
+            Ok((
+                texture_name.clone(),
+                TextureData::new(
+                    texture_name,
+                    rgba,
+                    data.width() as usize,
+                    data.height() as usize,
+                    transparency,
+                ),
             ))
-        }).collect();
+        })
+        .collect::<Result<_, _>>()?;
 
+    let model_dir_path = path.as_ref().join("models");
+    let mut model_count = 0;
+    let model_array: Vec<(String, ModelID, VoxScene)> = read_dir_sorted(&model_dir_path)?
+        .into_iter()
+        .filter(|path| path.is_file())
+        .map(|model_path| {
+            let model_name = file_name(&model_path);
+            let vox_data = dot_vox::load(&model_path.to_string_lossy())
+                .map_err(|e| ParseError::in_file(&model_path, ParseErrorKind::Vox(e)))?;
+            let scene = VoxScene::from_vox_data(&vox_data);
+            let first_id = ModelID(model_count);
+            model_count += scene.models.len();
+            Ok((model_name, first_id, scene))
+        })
+        .collect::<Result<_, _>>()?;
+
+    let mut texture_set = TextureSet {
+        named: &texture_array,
+        animations: Vec::new(),
+    };
     let blueprint_dir_path = path.as_ref().join("blueprints");
-    let blueprint_count = fs::read_dir(&blueprint_dir_path).expect("Couldn't find 'blueprints' dir").count();
-    let blueprint_dir = fs::read_dir(blueprint_dir_path).unwrap();
-    let mut blueprints = Vec::with_capacity(blueprint_count);
-    for blueprint_path in blueprint_dir.flatten() {
-        let blueprint_name = blueprint_path.file_name().to_str().unwrap().to_owned();
-        let tmx_path = blueprint_path.path().join(format!("{}.tmx", blueprint_name));
-        let tiled_data = Loader::new()
-            .load_tmx_map(tmx_path)
-            .unwrap();
-
-        let map_properties = &tiled_data.properties;
-        let PropertyValue::FloatValue(ambient_light) = map_properties.get("ambient_light").unwrap().to_owned() else { panic!()};
-        let PropertyValue::StringValue(skybox_north_name) = map_properties.get("skybox_north").unwrap() else { panic!()};
-        let PropertyValue::StringValue(skybox_east_name) = map_properties.get("skybox_east").unwrap() else { panic!()};
-        let PropertyValue::StringValue(skybox_south_name) = map_properties.get("skybox_south").unwrap() else { panic!()};
-        let PropertyValue::StringValue(skybox_west_name) = map_properties.get("skybox_west").unwrap() else { panic!()};
-        let PropertyValue::StringValue(skybox_top_name) = map_properties.get("skybox_top").unwrap() else { panic!()};
-        let PropertyValue::StringValue(skybox_bottom_name) = map_properties.get("skybox_bottom").unwrap() else { panic!()};
-
-        let skybox_north = texture_array.iter().position(|(name, _)| name == skybox_north_name);
-        let skybox_east = texture_array.iter().position(|(name, _)| name == skybox_east_name);
-        let skybox_south = texture_array.iter().position(|(name, _)| name == skybox_south_name);
-        let skybox_west = texture_array.iter().position(|(name, _)| name == skybox_west_name);
-        let skybox_top = texture_array.iter().position(|(name, _)| name == skybox_top_name);
-        let skybox_bottom = texture_array.iter().position(|(name, _)| name == skybox_bottom_name);
+    let mut blueprints = Vec::new();
+    for blueprint_path in read_dir_sorted(&blueprint_dir_path)? {
+        let blueprint_name = file_name(&blueprint_path);
+        let tmx_path = blueprint_path.join(format!("{}.tmx", blueprint_name));
+        let id = TilemapID(blueprints.len());
+        blueprints.push(parse_blueprint(
+            &tmx_path,
+            id,
+            blueprint_name,
+            &mut texture_set,
+            &model_array,
+        )?);
+    }
 
-        // TODO find a better solution instead of idx+1 everywhere
-        let default_skybox = Skybox {
-            north: skybox_north.map(|idx| TextureID(idx+1)).unwrap_or_default(),
-            east: skybox_east.map(|idx| TextureID(idx+1)).unwrap_or_default(),
-            south: skybox_south.map(|idx| TextureID(idx+1)).unwrap_or_default(),
-            west: skybox_west.map(|idx| TextureID(idx+1)).unwrap_or_default(),
-            top: skybox_top.map(|idx| TextureID(idx+1)).unwrap_or_default(),
-            bottom: skybox_bottom.map(|idx| TextureID(idx+1)).unwrap_or_default(),
-        };
+    let animations = texture_set.animations;
+    let textures = texture_array
+        .into_iter()
+        .map(|(_, texture_data)| texture_data)
+        .collect();
+    let models = model_array
+        .into_iter()
+        .flat_map(|(_, _, scene)| scene.models)
+        .map(|(_, model_data)| model_data)
+        .collect();
 
-        let TileLayer::Finite(tile_layer) =
-            tiled_data.get_layer(0).unwrap().as_tile_layer().unwrap()
-        else {
-            panic!()
-        };
+    Ok((blueprints, textures, animations, models))
+}
+
+fn parse_blueprint(
+    tmx_path: &Path,
+    id: TilemapID,
+    name: String,
+    texture_set: &mut TextureSet,
+    model_array: &NamedModels,
+) -> Result<Tilemap, ParseError> {
+    let tiled_data = Loader::new()
+        .load_tmx_map(tmx_path)
+        .map_err(|e| ParseError::in_file(tmx_path, ParseErrorKind::Tiled(e)))?;
+
+    let map_properties =
+        PropertyReader::new(&tiled_data.properties, tmx_path, ErrorLocation::Map);
+    let ambient_light = map_properties.float("ambient_light")?;
+    // Blueprints can be used for generating rooms unless stated otherwise
+    let repeatable = map_properties.optional_bool("repeatable")?.unwrap_or(true);
+    let default_rules = GenerationRules::default();
+    let weight = map_properties
+        .optional_float("weight")?
+        .unwrap_or(default_rules.weight);
+    if !weight.is_finite() || weight < 0.0 {
+        return Err(map_properties.error(ParseErrorKind::InvalidValue {
+            property: "weight".to_owned(),
+            value: weight.to_string(),
+            expected: "a finite number of at least 0",
+        }));
+    }
+    let mut generation_rules = GenerationRules {
+        weight,
+        max_occurrences: map_properties.optional_uint("max_occurrences")?,
+        min_depth: map_properties
+            .optional_uint("min_depth")?
+            .unwrap_or(default_rules.min_depth),
+        tags: map_properties.tags("tags")?,
+        neighbour_tags: map_properties.tags("neighbour_tags")?,
+        portal_tags: Vec::new(),
+    };
+    // Tags of portals which don't have their own
+    let default_portal_tags = map_properties.tags("portal_tags")?;
+    let default_skybox = Skybox {
+        north: map_properties.texture("skybox_north", texture_set)?,
+        east: map_properties.texture("skybox_east", texture_set)?,
+        south: map_properties.texture("skybox_south", texture_set)?,
+        west: map_properties.texture("skybox_west", texture_set)?,
+        top: map_properties.texture("skybox_top", texture_set)?,
+        bottom: map_properties.texture("skybox_bottom", texture_set)?,
+    };
+
+    // Fog is optional and doesn't appear if no density is specified
+    let default_fog = match map_properties.optional_float("fog_density")? {
+        Some(density) => Fog {
+            color: map_properties
+                .optional_color("fog_color")?
+                .unwrap_or(Vec3::ZERO),
+            start: map_properties.optional_float("fog_start")?.unwrap_or(0.0),
+            density,
+        },
+        None => Fog::default(),
+    };
+
+    let mut tile_layers = tiled_data
+        .layers()
+        .filter_map(|layer| layer.as_tile_layer());
+    let tile_layer = match tile_layers.next() {
+        Some(TileLayer::Finite(tile_layer)) => tile_layer,
+        Some(TileLayer::Infinite(_)) => {
+            return Err(ParseError::in_file(
+                tmx_path,
+                ParseErrorKind::InfiniteTileLayer,
+            ))
+        }
+        None => {
+            return Err(ParseError::in_file(
+                tmx_path,
+                ParseErrorKind::MissingTileLayer,
+            ))
+        }
+    };
 
-        let width = tile_layer.width() as i32;
-        let height = tile_layer.height() as i32; 
-        let mut tiles = Vec::with_capacity((width * height) as usize);
-        let mut portals = Vec::new();
+    let width = tile_layer.width() as i32;
+    let height = tile_layer.height() as i32;
+    let mut tiles = Vec::with_capacity((width * height) as usize);
+    let mut portals = Vec::new();
+    let mut lights = Vec::new();
+    let mut object_placeholders = Vec::new();
+    // Corners of the box around each object
+    let mut object_bounds: Vec<(Vec3, Vec3)> = Vec::new();
+    let mut movers: Vec<Mover> = Vec::new();
+    let mut mover_triggers = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            // Reverse the y direction
+            let tiled_y = height - y - 1;
+            let layer_tile = tile_layer.get_tile(x, tiled_y);
+            let location = ErrorLocation::Tile {
+                id: layer_tile.as_ref().map(|tile| tile.id()),
+                x,
+                y: tiled_y,
+            };
+            let Some(tile_data) = layer_tile.as_ref().and_then(|tile| tile.get_tile())
+            else {
+                return Err(ParseError {
+                    file: tmx_path.to_owned(),
+                    location,
+                    kind: ParseErrorKind::EmptyTile,
+                });
+            };
+            let tile_properties =
+                PropertyReader::new(&tile_data.properties, tmx_path, location);
+            let position = PointXZ {
+                x: x as u64,
+                z: y as u64,
+            };
+            let mut tile = parse_span(&tile_properties, position, texture_set)?;
+            animate_tile(&mut tile, &tile_data, tmx_path, (x, tiled_y), texture_set)?;
+            let ground_height = tile.ground_height;
+            let portal_direction = tile_properties.string("portal_direction")?;
+
+            // Tiles with the model property hold a voxel scene standing on the
+            // ground, centered on the tile. By default the scene fits in the tile.
+            let scene = tile_properties.model("model", model_array)?;
+            if let Some((first_id, scene)) = scene {
+                let voxels_per_tile = tile_properties
+                    .optional_float("model_voxels_per_tile")?
+                    .unwrap_or(scene.size.x.max(scene.size.z).max(1) as f32);
+                let voxel_size = voxels_per_tile.recip();
+                let voxel_scale =
+                    Vec3::new(voxel_size, voxel_size * VOXEL_HEIGHT_SCALE, voxel_size);
+                let scene_corner = Vec3::new(
+                    position.x as f32 + 0.5 - scene.size.x as f32 * voxel_size * 0.5,
+                    ground_height,
+                    position.z as f32 + 0.5 - scene.size.z as f32 * voxel_size * 0.5,
+                );
+                // Each model of the scene is a separate object at its place
+                for (i, (offset, model)) in scene.models.iter().enumerate() {
+                    let corner = scene_corner + offset.as_vec3() * voxel_scale;
+                    object_placeholders.push(Some(Object {
+                        model: ModelID(first_id.0 + i),
+                        position: corner,
+                        voxel_size,
+                    }));
+                    let end = corner + model.dimensions().as_vec3() * voxel_scale;
+                    object_bounds.push((corner, end));
+                }
+            }
+
+            // Tiles with the light intensity property hold a point light
+            if let Some(intensity) = tile_properties.optional_float("light_intensity")? {
+                let ground = Vec3::new(
+                    position.x as f32 + 0.5,
+                    ground_height,
+                    position.z as f32 + 0.5,
+                );
+                lights.push(tile_properties.point_light(ground, intensity)?);
+            }
+            let portal_id = if let Some(direction) =
+                tile_properties.parse_direction("portal_direction", portal_direction)?
+            {
+                let id = PortalID(portals.len());
+                let portal_tags = tile_properties.tags("portal_tags")?;
+                generation_rules.portal_tags.push(if portal_tags.is_empty() {
+                    default_portal_tags.clone()
+                } else {
+                    portal_tags
+                });
+                let scale = tile_properties.optional_float("portal_scale")?.unwrap_or(1.0);
+                if scale <= 0.0 {
+                    return Err(tile_properties.error(ParseErrorKind::InvalidValue {
+                        property: "portal_scale".to_owned(),
+                        value: scale.to_string(),
+                        expected: "a number greater than 0",
+                    }));
+                }
+                let portal = Portal {
+                    id,
+                    direction,
+                    position,
+                    center: Vec2::new(position.x as f32 + 0.5, position.z as f32 + 0.5),
+                    ground_height,
+                    yaw: tile_properties
+                        .optional_float("portal_yaw")?
+                        .unwrap_or(0.0)
+                        .to_radians(),
+                    scale,
+                    height_offset: tile_properties
+                        .optional_float("portal_height_offset")?
+                        .unwrap_or(0.0),
+                    destination: None,
+                };
+                portals.push(portal);
+                Some(id)
+            } else {
+                None
+            };
+
+            // Tiles with the same mover name move together and the first
+            // of them sets the timing of the mover
+            if let Some(mover_name) = tile_properties.optional_string("mover")? {
+                let id = match movers.iter().position(|mover| mover.name == *mover_name) {
+                    Some(id) => id,
+                    None => {
+                        let is_timer = tile_properties
+                            .optional_bool("mover_timer")?
+                            .unwrap_or(false);
+                        movers.push(Mover {
+                            name: mover_name.to_owned(),
+                            tiles: Vec::new(),
+                            travel_time: tile_properties
+                                .optional_float("mover_travel_time")?
+                                .unwrap_or(DEFAULT_MOVER_TRAVEL_TIME),
+                            wait_time: tile_properties
+                                .optional_float("mover_wait_time")?
+                                .unwrap_or(DEFAULT_MOVER_WAIT_TIME),
+                            activation: match is_timer {
+                                true => Activation::Timer,
+                                false => Activation::Trigger,
+                            },
+                        });
+                        movers.len() - 1
+                    }
+                };
+                movers[id].tiles.push(MovedTile {
+                    index: tiles.len(),
+                    ground_offset: tile_properties
+                        .optional_float("mover_ground_offset")?
+                        .unwrap_or(0.0),
+                    ceiling_offset: tile_properties
+                        .optional_float("mover_ceiling_offset")?
+                        .unwrap_or(0.0),
+                    top_offset: tile_properties
+                        .optional_float("mover_top_offset")?
+                        .unwrap_or(0.0),
+                });
+            }
+            if let Some(mover_name) = tile_properties.optional_string("mover_trigger")? {
+                mover_triggers.push((tiles.len(), mover_name.to_owned(), location));
+            }
+
+            tile.portal_id = portal_id;
+            tiles.push(tile);
+        }
+    }
+
+    // Every other tile layer stacks a span on its tiles, where they aren't empty
+    let mut span_layers = Vec::new();
+    for layer in tile_layers {
+        let TileLayer::Finite(layer) = layer else {
+            return Err(ParseError::in_file(
+                tmx_path,
+                ParseErrorKind::InfiniteTileLayer,
+            ));
+        };
+        let mut spans = Vec::with_capacity(tiles.len());
         for y in 0..height {
             for x in 0..width {
-                // Reverse the y direction
-                let tile_properties = &tile_layer.get_tile(x, height - y - 1).unwrap().get_tile().unwrap().properties;
-                let PropertyValue::FloatValue(bottom_height) = tile_properties.get("bottom_height").unwrap().to_owned() else { panic!()};
-                let PropertyValue::FloatValue(ground_height) = tile_properties.get("ground_height").unwrap().to_owned() else { panic!()};
-                let PropertyValue::FloatValue(ceiling_height) = tile_properties.get("ceiling_height").unwrap().to_owned() else { panic!()};
-                let PropertyValue::FloatValue(top_height) = tile_properties.get("top_height").unwrap().to_owned() else { panic!()};
-                let PropertyValue::StringValue(bottom_texture_name) = tile_properties.get("bottom_texture").unwrap() else { panic!()};
-                let PropertyValue::StringValue(ground_texture_name) = tile_properties.get("ground_texture").unwrap() else { panic!()};
-                let PropertyValue::StringValue(ceiling_texture_name) = tile_properties.get("ceiling_texture").unwrap() else { panic!()};
-                let PropertyValue::StringValue(top_texture_name) = tile_properties.get("top_texture").unwrap() else { panic!()};
-                let PropertyValue::StringValue(portal_direction) = tile_properties.get("portal_direction").unwrap() else {panic!()};
-                
-                let bottom_texture = texture_array.iter().position(|(name, _)| name == bottom_texture_name);
-                let ground_texture = texture_array.iter().position(|(name, _)| name == ground_texture_name);
-                let ceiling_texture = texture_array.iter().position(|(name, _)| name == ceiling_texture_name);
-                let top_texture = texture_array.iter().position(|(name, _)| name == top_texture_name);
-                
-                let position = PointXZ { x: x as u64, z: y as u64 };
-                let portal_id = if !portal_direction.is_empty() {
-                    let direction = match portal_direction.as_str() {
-                        "N" => Vec2::Y,
-                        "E" => Vec2::X,
-                        "S" => Vec2::NEG_Y,
-                        "W" => Vec2::NEG_X,
-                        _ => panic!()
+                let tiled_y = height - y - 1;
+                let layer_tile = layer.get_tile(x, tiled_y);
+                let Some(tile_data) =
+                    layer_tile.as_ref().and_then(|tile| tile.get_tile())
+                else {
+                    spans.push(None);
+                    continue;
+                };
+                let location = ErrorLocation::Tile {
+                    id: layer_tile.as_ref().map(|tile| tile.id()),
+                    x,
+                    y: tiled_y,
+                };
+                let span_properties =
+                    PropertyReader::new(&tile_data.properties, tmx_path, location);
+                let position = PointXZ {
+                    x: x as u64,
+                    z: y as u64,
+                };
+                let mut span = parse_span(&span_properties, position, texture_set)?;
+                animate_tile(&mut span, &tile_data, tmx_path, (x, tiled_y), texture_set)?;
+                spans.push(Some(span));
+            }
+        }
+        span_layers.push(spans);
+    }
+
+    // Trigger tiles can name the movers defined further in the tile layer
+    for (index, mover_name, location) in mover_triggers {
+        let Some(id) = movers.iter().position(|mover| mover.name == *mover_name) else {
+            return Err(ParseError {
+                file: tmx_path.to_owned(),
+                location,
+                kind: ParseErrorKind::UnknownMover {
+                    property: "mover_trigger".to_owned(),
+                    name: mover_name.to_owned(),
+                },
+            });
+        };
+        tiles[index].mover_trigger = Some(MoverID(id));
+    }
+
+    // Models bigger than a tile also cover the surrounding tiles,
+    // where they can overlap the other models
+    for (idx, (start, end)) in object_bounds.into_iter().enumerate() {
+        let (from_x, to_x) = (
+            (start.x.floor() as i32).max(0),
+            (end.x.ceil() as i32).min(width),
+        );
+        let (from_z, to_z) = (
+            (start.z.floor() as i32).max(0),
+            (end.z.ceil() as i32).min(height),
+        );
+        for z in from_z..to_z {
+            for x in from_x..to_x {
+                tiles[(z * width + x) as usize].objects.push(ObjectID(idx));
+            }
+        }
+    }
+
+    // Typed objects are placed in any of the object layers, by their class
+    let tile_width = tiled_data.tile_width as f32;
+    let tile_height = tiled_data.tile_height as f32;
+    let mut sprites = Vec::new();
+    let mut spawn = None;
+    let mut triggers = Vec::new();
+    let mut spawners = Vec::new();
+    for object_layer in tiled_data
+        .layers()
+        .filter_map(|layer| layer.as_object_layer())
+    {
+        for object in object_layer.objects() {
+            let object_properties = PropertyReader::new(
+                &object.properties,
+                tmx_path,
+                ErrorLocation::Object { id: object.id() },
+            );
+
+            // Rectangle objects are positioned by their top left corner
+            let (x, y) = match object.shape {
+                ObjectShape::Rect { width, height } => {
+                    (object.x + width * 0.5, object.y + height * 0.5)
+                }
+                _ => (object.x, object.y),
+            };
+            // Reverse the y direction
+            let x = x / tile_width;
+            let z = height as f32 - y / tile_height;
+            let tile_x = (x as i32).clamp(0, width - 1);
+            let tile_z = (z as i32).clamp(0, height - 1);
+            let ground_height = tiles[(tile_z * width + tile_x) as usize].ground_height;
+
+            match object.user_type.as_str() {
+                SPRITE_CLASS => {
+                    let texture = object_properties.texture("texture", texture_set)?;
+                    let sprite_width = object_properties
+                        .optional_float("width")?
+                        .unwrap_or(DEFAULT_SPRITE_SIZE);
+                    let sprite_height = object_properties
+                        .optional_float("height")?
+                        .unwrap_or(DEFAULT_SPRITE_SIZE);
+                    let elevation = object_properties
+                        .optional_float("elevation")?
+                        .unwrap_or(0.0);
+                    sprites.push(Sprite {
+                        position: Vec3::new(x, ground_height + elevation, z),
+                        width: sprite_width,
+                        height: sprite_height,
+                        texture,
+                    });
+                }
+                // Only the first spawn is used
+                SPAWN_CLASS if spawn.is_none() => {
+                    spawn = Some(Spawn {
+                        position: Vec3::new(x, ground_height, z),
+                        yaw: object_properties.facing("facing")?,
+                    });
+                }
+                TRIGGER_CLASS => {
+                    let ObjectShape::Rect {
+                        width: object_width,
+                        height: object_height,
+                    } = object.shape
+                    else {
+                        return Err(object_properties
+                            .error(ParseErrorKind::WrongObjectShape("rectangle")));
                     };
-                    let id = PortalID(portals.len());
-                    let portal = Portal {
-                        id,
-                        direction,
-                        position,
-                        center: Vec2::new(
-                            position.x as f32 + 0.5,
-                            position.z as f32 + 0.5,
-                        ),
-                        ground_height,
-                        destination: None,
+                    // Triggers reach from the bottom to the top of the room by default
+                    let bottom = object_properties
+                        .optional_float("bottom_height")?
+                        .unwrap_or(f32::NEG_INFINITY);
+                    let top = object_properties
+                        .optional_float("top_height")?
+                        .unwrap_or(f32::INFINITY);
+                    let mover = match object_properties.optional_string("mover")? {
+                        Some(mover_name) => {
+                            let Some(id) =
+                                movers.iter().position(|mover| mover.name == *mover_name)
+                            else {
+                                return Err(object_properties.error(
+                                    ParseErrorKind::UnknownMover {
+                                        property: "mover".to_owned(),
+                                        name: mover_name.to_owned(),
+                                    },
+                                ));
+                            };
+                            Some(MoverID(id))
+                        }
+                        None => None,
                     };
-                    portals.push(portal);
-                    Some(id)
-                } else { None };
+                    triggers.push(Trigger {
+                        min: Vec3::new(
+                            object.x / tile_width,
+                            bottom,
+                            height as f32 - (object.y + object_height) / tile_height,
+                        ),
+                        max: Vec3::new(
+                            (object.x + object_width) / tile_width,
+                            top,
+                            height as f32 - object.y / tile_height,
+                        ),
+                        mover,
+                        properties: object.properties.clone(),
+                    });
+                }
+                SPAWNER_CLASS => {
+                    let elevation = object_properties
+                        .optional_float("elevation")?
+                        .unwrap_or(0.0);
+                    spawners.push(Spawner {
+                        entity: object_properties.string("entity")?.to_owned(),
+                        position: Vec3::new(x, ground_height + elevation, z),
+                        yaw: object_properties.facing("facing")?,
+                        properties: object.properties.clone(),
+                    });
+                }
+                LIGHT_CLASS => {
+                    let intensity = object_properties
+                        .optional_float("light_intensity")?
+                        .unwrap_or(DEFAULT_LIGHT_INTENSITY);
+                    let ground = Vec3::new(x, ground_height, z);
+                    lights.push(object_properties.point_light(ground, intensity)?);
+                }
+                _ => (),
+            }
+        }
+    }
 
-                let tile = Tile {
-                    position,
-                    bottom_wall_tex: bottom_texture.map(|idx| TextureID(idx+1)).unwrap_or_default(),
-                    top_wall_tex: top_texture.map(|idx| TextureID(idx+1)).unwrap_or_default(),
-                    ground_tex: ground_texture.map(|idx| TextureID(idx+1)).unwrap_or_default(),
-                    ceiling_tex: ceiling_texture.map(|idx| TextureID(idx+1)).unwrap_or_default(),
-                    bottom_height,
-                    ground_height,
-                    ceiling_height,
-                    top_height,
-                    portal_id,
-                    object: None,
-                };
-                tiles.push(tile);
+    let dimensions = (width as u64, height as u64);
+    let lightmap = lightmap::bake(&tiles, &span_layers, dimensions, &lights);
+    let span_lightmaps = lightmap::bake_spans(&tiles, &span_layers, dimensions, &lights);
+
+    Ok(Tilemap {
+        id,
+        name,
+        dimensions,
+        tiles,
+        span_layers,
+        unlinked_portals: portals,
+        default_skybox,
+        repeatable,
+        generation_rules,
+        default_ambient_light: ambient_light,
+        default_fog,
+        lightmap,
+        span_lightmaps,
+        sprite_placeholders: sprites,
+        object_placeholders,
+        movers,
+        spawn,
+        trigger_placeholders: triggers,
+        spawner_placeholders: spawners,
+        light_placeholders: lights,
+    })
+}
+
+/// Reads the heights, textures, texture mappings and slopes of a tile. Portals,
+/// objects and movers are left for the caller, since stacked spans have none.
+fn parse_span(
+    properties: &PropertyReader,
+    position: PointXZ<u64>,
+    texture_set: &mut TextureSet,
+) -> Result<Tile, ParseError> {
+    Ok(Tile {
+        position,
+        bottom_wall_texs: properties.wall_textures("bottom_texture", texture_set)?,
+        top_wall_texs: properties.wall_textures("top_texture", texture_set)?,
+        ground_tex: properties.texture("ground_texture", texture_set)?,
+        ceiling_tex: properties.texture("ceiling_texture", texture_set)?,
+        bottom_wall_mapping: properties.texture_mapping("bottom_texture")?,
+        top_wall_mapping: properties.texture_mapping("top_texture")?,
+        ground_mapping: properties.texture_mapping("ground_texture")?,
+        ceiling_mapping: properties.texture_mapping("ceiling_texture")?,
+        bottom_height: properties.float("bottom_height")?,
+        ground_height: properties.float("ground_height")?,
+        ceiling_height: properties.float("ceiling_height")?,
+        top_height: properties.float("top_height")?,
+        portal_id: None,
+        ground_slope: properties.slope("ground_slope", "ground_slope_rise")?,
+        ceiling_slope: properties.slope("ceiling_slope", "ceiling_slope_rise")?,
+        mover_trigger: None,
+        objects: Vec::new(),
+    })
+}
+
+/// Animates the textures of the tile if it has a Tiled animation. Each face
+/// shows the texture of the same face of every frame tile for the frame duration.
+fn animate_tile(
+    tile: &mut Tile,
+    tile_data: &tiled::Tile,
+    file: &Path,
+    (x, y): (i32, i32),
+    texture_set: &mut TextureSet,
+) -> Result<(), ParseError> {
+    let Some(frames) = tile_data
+        .animation
+        .as_ref()
+        .filter(|frames| !frames.is_empty())
+    else {
+        return Ok(());
+    };
+    let no_properties = Properties::new();
+    let mut frame_textures = Vec::with_capacity(frames.len());
+    for frame in frames {
+        // Frame tiles need the same properties as every other tile
+        let frame_tile = tile_data.tileset().get_tile(frame.tile_id);
+        let properties = PropertyReader::new(
+            frame_tile
+                .as_ref()
+                .map_or(&no_properties, |frame_tile| &frame_tile.properties),
+            file,
+            ErrorLocation::Tile {
+                id: Some(frame.tile_id),
+                x,
+                y,
+            },
+        );
+        let textures: Vec<TextureID> =
+            parse_span(&properties, tile.position, texture_set)?
+                .textures()
+                .collect();
+        frame_textures.push((textures, frame.duration as f32 / 1000.0));
+    }
+
+    for (face, texture) in tile.textures_mut().enumerate() {
+        let frames: Vec<(TextureID, f32)> = frame_textures
+            .iter()
+            .map(|(textures, duration)| (textures[face], *duration))
+            .collect();
+        *texture = if frames.iter().all(|(id, _)| *id == frames[0].0) {
+            frames[0].0
+        } else {
+            texture_set.animation_id(TextureAnimation::Frames(frames))
+        };
+    }
+    Ok(())
+}
+
+/// Returns paths of all entries in the directory sorted by name, so
+/// the IDs are the same on every platform and every run.
+fn read_dir_sorted(dir_path: &Path) -> Result<Vec<PathBuf>, ParseError> {
+    let dir = fs::read_dir(dir_path)
+        .map_err(|e| ParseError::in_file(dir_path, ParseErrorKind::Io(e)))?;
+    let mut paths: Vec<PathBuf> = dir.flatten().map(|entry| entry.path()).collect();
+    paths.sort();
+    Ok(paths)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Converts the Tiled color into a linear RGB color with each
+/// channel in range [0.0, 1.0]. Alpha is ignored.
+fn color_to_vec3(color: tiled::Color) -> Vec3 {
+    Vec3::new(color.red as f32, color.green as f32, color.blue as f32) / 255.0
+}
+
+/// Reads the custom properties of one part of a blueprint and
+/// remembers where they come from, so errors point to the exact place.
+struct PropertyReader<'a> {
+    properties: &'a Properties,
+    file: &'a Path,
+    location: ErrorLocation,
+}
+
+impl<'a> PropertyReader<'a> {
+    fn new(properties: &'a Properties, file: &'a Path, location: ErrorLocation) -> Self {
+        Self {
+            properties,
+            file,
+            location,
+        }
+    }
+
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            file: self.file.to_owned(),
+            location: self.location,
+            kind,
+        }
+    }
+
+    fn get(&self, name: &str) -> Result<&'a PropertyValue, ParseError> {
+        self.properties
+            .get(name)
+            .ok_or_else(|| self.error(ParseErrorKind::MissingProperty(name.to_owned())))
+    }
+
+    fn wrong_type(&self, name: &str, expected: &'static str) -> ParseError {
+        self.error(ParseErrorKind::WrongPropertyType {
+            property: name.to_owned(),
+            expected,
+        })
+    }
+
+    fn float(&self, name: &str) -> Result<f32, ParseError> {
+        match self.get(name)? {
+            PropertyValue::FloatValue(value) => Ok(*value),
+            PropertyValue::IntValue(value) => Ok(*value as f32),
+            _ => Err(self.wrong_type(name, "float")),
+        }
+    }
+
+    fn string(&self, name: &str) -> Result<&'a String, ParseError> {
+        match self.get(name)? {
+            PropertyValue::StringValue(value) => Ok(value),
+            _ => Err(self.wrong_type(name, "string")),
+        }
+    }
+
+    /// Returns the value of the optional property. An empty value stands for none.
+    fn optional_string(&self, name: &str) -> Result<Option<&'a String>, ParseError> {
+        match self.properties.get(name) {
+            Some(PropertyValue::StringValue(value)) if value.is_empty() => Ok(None),
+            Some(PropertyValue::StringValue(value)) => Ok(Some(value)),
+            Some(_) => Err(self.wrong_type(name, "string")),
+            None => Ok(None),
+        }
+    }
+
+    fn optional_float(&self, name: &str) -> Result<Option<f32>, ParseError> {
+        if self.properties.contains_key(name) {
+            self.float(name).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn optional_uint(&self, name: &str) -> Result<Option<u32>, ParseError> {
+        match self.properties.get(name) {
+            Some(PropertyValue::IntValue(value)) => u32::try_from(*value)
+                .map(Some)
+                .map_err(|_| self.wrong_type(name, "non-negative int")),
+            Some(_) => Err(self.wrong_type(name, "int")),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the comma separated tags from the optional property.
+    fn tags(&self, name: &str) -> Result<Vec<String>, ParseError> {
+        match self.properties.get(name) {
+            Some(PropertyValue::StringValue(value)) => Ok(value
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_owned)
+                .collect()),
+            Some(_) => Err(self.wrong_type(name, "string")),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn optional_bool(&self, name: &str) -> Result<Option<bool>, ParseError> {
+        match self.properties.get(name) {
+            Some(PropertyValue::BoolValue(value)) => Ok(Some(*value)),
+            Some(_) => Err(self.wrong_type(name, "bool")),
+            None => Ok(None),
+        }
+    }
+
+    fn optional_color(&self, name: &str) -> Result<Option<Vec3>, ParseError> {
+        match self.properties.get(name) {
+            Some(PropertyValue::ColorValue(color)) => Ok(Some(color_to_vec3(*color))),
+            Some(_) => Err(self.wrong_type(name, "color")),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the direction of the property value, which is one of
+    /// 'N', 'E', 'S' and 'W'. An empty value stands for no direction.
+    fn parse_direction(
+        &self,
+        name: &str,
+        value: &str,
+    ) -> Result<Option<Vec2>, ParseError> {
+        match value {
+            "" => Ok(None),
+            "N" => Ok(Some(Vec2::Y)),
+            "E" => Ok(Some(Vec2::X)),
+            "S" => Ok(Some(Vec2::NEG_Y)),
+            "W" => Ok(Some(Vec2::NEG_X)),
+            _ => Err(self.error(ParseErrorKind::InvalidValue {
+                property: name.to_owned(),
+                value: value.to_owned(),
+                expected: "one of 'N', 'E', 'S', 'W' or empty",
+            })),
+        }
+    }
+
+    /// Returns the slope which rises towards the direction of the optional
+    /// property by the value of the optional rise property.
+    fn slope(&self, direction_name: &str, rise_name: &str) -> Result<Slope, ParseError> {
+        let direction = if self.properties.contains_key(direction_name) {
+            self.parse_direction(direction_name, self.string(direction_name)?)?
+        } else {
+            None
+        };
+        let rise = self.optional_float(rise_name)?.unwrap_or(0.0);
+        Ok(match direction {
+            Some(direction) => Slope::new(direction, rise),
+            None => Slope::default(),
+        })
+    }
+
+    /// Returns the yaw in radians of the direction from the optional property.
+    /// Faces north by default.
+    fn facing(&self, name: &str) -> Result<f32, ParseError> {
+        let direction = if self.properties.contains_key(name) {
+            self.parse_direction(name, self.string(name)?)?
+        } else {
+            None
+        };
+        let direction = direction.unwrap_or(Vec2::Y);
+        Ok(f32::atan2(direction.y, direction.x))
+    }
+
+    /// Returns the light which shines above the `ground` point, with the
+    /// color, radius and height above the ground from the optional properties.
+    fn point_light(
+        &self,
+        ground: Vec3,
+        intensity: f32,
+    ) -> Result<PointLight, ParseError> {
+        let color = self
+            .optional_color("light_color")?
+            .unwrap_or(DEFAULT_LIGHT_COLOR);
+        let radius = self
+            .optional_float("light_radius")?
+            .unwrap_or(DEFAULT_LIGHT_RADIUS);
+        let height = self
+            .optional_float("light_height")?
+            .unwrap_or(DEFAULT_LIGHT_HEIGHT);
+        Ok(PointLight {
+            position: ground + Vec3::Y * height,
+            color,
+            radius,
+            intensity,
+            properties: self.properties.clone(),
+        })
+    }
+
+    /// Returns the texture with the file name from the property.
+    /// An empty name stands for the default texture.
+    fn texture(
+        &self,
+        name: &str,
+        texture_set: &mut TextureSet,
+    ) -> Result<TextureID, ParseError> {
+        let texture_name = self.string(name)?;
+        if texture_name.is_empty() {
+            return Ok(TextureID::default());
+        }
+        self.find_texture(name, texture_name, texture_set)
+    }
+
+    /// Returns the texture with the file name from the optional property.
+    /// A missing property or an empty name stands for no texture.
+    fn optional_texture(
+        &self,
+        name: &str,
+        texture_set: &mut TextureSet,
+    ) -> Result<Option<TextureID>, ParseError> {
+        self.optional_string(name)?
+            .map(|texture_name| self.find_texture(name, texture_name, texture_set))
+            .transpose()
+    }
+
+    fn find_texture(
+        &self,
+        name: &str,
+        texture_name: &str,
+        texture_set: &mut TextureSet,
+    ) -> Result<TextureID, ParseError> {
+        // TODO find a better solution instead of idx+1 everywhere
+        let texture = texture_set
+            .named
+            .iter()
+            .position(|(name, _)| name == texture_name)
+            .map(|idx| TextureID(idx + 1))
+            .ok_or_else(|| {
+                self.error(ParseErrorKind::UnknownTexture {
+                    property: name.to_owned(),
+                    name: texture_name.to_owned(),
+                })
+            })?;
+
+        // Textures with the optional frame rate are played as vertical strips
+        let fps_name = format!("{}_fps", name);
+        match self.optional_float(&fps_name)? {
+            Some(fps) if fps <= 0.0 => Err(self.error(ParseErrorKind::InvalidValue {
+                property: fps_name,
+                value: fps.to_string(),
+                expected: "a number greater than 0",
+            })),
+            Some(fps) => {
+                Ok(texture_set.animation_id(TextureAnimation::Strip { texture, fps }))
+            }
+            None => Ok(texture),
+        }
+    }
+
+    /// Returns the textures of the wall sides in the order: north, east, south,
+    /// west. Each side can override the texture from the property with its own
+    /// optional property, named with the side appended, like `top_texture_north`.
+    fn wall_textures(
+        &self,
+        name: &str,
+        texture_set: &mut TextureSet,
+    ) -> Result<[TextureID; 4], ParseError> {
+        let texture = self.texture(name, texture_set)?;
+        let mut textures = [texture; 4];
+        for (side, side_name) in
+            textures.iter_mut().zip(["north", "east", "south", "west"])
+        {
+            let side_property = format!("{}_{}", name, side_name);
+            if let Some(side_texture) =
+                self.optional_texture(&side_property, texture_set)?
+            {
+                *side = side_texture;
             }
         }
+        Ok(textures)
+    }
 
-        let blueprint = Tilemap {
-            id: TilemapID(blueprints.len()),
-            dimensions: (width as u64, height as u64),
-            tiles,
-            unlinked_portals: portals,
-            default_skybox,
-            repeatable: false,
-            default_ambient_light: ambient_light,
+    /// Returns how the texture from the property is laid on the face, from the
+    /// optional properties named with the suffixes: `_offset_u` and `_offset_v`
+    /// in texture sizes, `_scale` and `_rotation` in degrees.
+    fn texture_mapping(&self, name: &str) -> Result<TextureMapping, ParseError> {
+        let offset = Vec2::new(
+            self.optional_float(&format!("{}_offset_u", name))?
+                .unwrap_or(0.0),
+            self.optional_float(&format!("{}_offset_v", name))?
+                .unwrap_or(0.0),
+        );
+        let scale_name = format!("{}_scale", name);
+        let scale = self.optional_float(&scale_name)?.unwrap_or(1.0);
+        if scale <= 0.0 {
+            return Err(self.error(ParseErrorKind::InvalidValue {
+                property: scale_name,
+                value: scale.to_string(),
+                expected: "a number greater than 0",
+            }));
+        }
+        let rotation = self
+            .optional_float(&format!("{}_rotation", name))?
+            .unwrap_or(0.0)
+            .to_radians();
+        Ok(TextureMapping::new(offset, scale, rotation))
+    }
+
+    /// Returns the vox scene with the file name from the optional property, with
+    /// the ID of its first model. A missing property or an empty name stands for
+    /// no scene.
+    fn model<'m>(
+        &self,
+        name: &str,
+        model_array: &'m NamedModels,
+    ) -> Result<Option<(ModelID, &'m VoxScene)>, ParseError> {
+        let model_name = match self.properties.get(name) {
+            Some(PropertyValue::StringValue(model_name)) => model_name,
+            Some(_) => return Err(self.wrong_type(name, "string")),
+            None => return Ok(None),
         };
-        blueprints.push(blueprint);
+        if model_name.is_empty() {
+            return Ok(None);
+        }
+        model_array
+            .iter()
+            .find(|(name, _, _)| name == model_name)
+            .map(|(_, first_id, scene)| Some((*first_id, scene)))
+            .ok_or_else(|| {
+                self.error(ParseErrorKind::UnknownModel {
+                    property: name.to_owned(),
+                    name: model_name.to_owned(),
+                })
+            })
+    }
+}
+
+/// Error found while parsing the map data, with the file and
+/// the part of the file in which it was found.
+#[derive(Debug)]
+pub struct ParseError {
+    pub file: PathBuf,
+    pub location: ErrorLocation,
+    pub kind: ParseErrorKind,
+}
+
+impl ParseError {
+    fn in_file(file: &Path, kind: ParseErrorKind) -> Self {
+        Self {
+            file: file.to_owned(),
+            location: ErrorLocation::File,
+            kind,
+        }
     }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ErrorLocation {
+    /// The file as a whole.
+    File,
+    /// Custom properties of the map.
+    Map,
+    /// A tile of the tile layer. Coordinates are the same as shown in Tiled.
+    /// Tile `id` is the id in the tileset and is missing for empty tiles.
+    Tile { id: Option<u32>, x: i32, y: i32 },
+    /// An object of an object layer.
+    Object { id: u32 },
+}
+
+#[derive(Debug)]
+pub enum ParseErrorKind {
+    Io(io::Error),
+    Image(image::ImageError),
+    Tiled(tiled::Error),
+    Vox(&'static str),
+    MissingTileLayer,
+    InfiniteTileLayer,
+    /// The tile has no tileset tile or the tileset tile has no properties.
+    EmptyTile,
+    MissingProperty(String),
+    WrongPropertyType {
+        property: String,
+        expected: &'static str,
+    },
+    InvalidValue {
+        property: String,
+        value: String,
+        expected: &'static str,
+    },
+    UnknownTexture {
+        property: String,
+        name: String,
+    },
+    UnknownModel {
+        property: String,
+        name: String,
+    },
+    UnknownMover {
+        property: String,
+        name: String,
+    },
+    /// The object has a different shape than the one its class needs.
+    WrongObjectShape(&'static str),
+}
 
-    let textures = texture_array.into_iter().map(|(_, texture_data)| texture_data).collect();
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        match self.location {
+            ErrorLocation::File => (),
+            ErrorLocation::Map => write!(f, ", map properties")?,
+            ErrorLocation::Tile { id: Some(id), x, y } => {
+                write!(f, ", tile {} at ({}, {})", id, x, y)?
+            }
+            ErrorLocation::Tile { id: None, x, y } => {
+                write!(f, ", tile at ({}, {})", x, y)?
+            }
+            ErrorLocation::Object { id } => write!(f, ", object {}", id)?,
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
 
-    (blueprints, textures)
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::Io(e) => write!(f, "{}", e),
+            ParseErrorKind::Image(e) => write!(f, "couldn't load the image: {}", e),
+            ParseErrorKind::Tiled(e) => write!(f, "couldn't load the Tiled map: {}", e),
+            ParseErrorKind::Vox(e) => write!(f, "couldn't load the vox model: {}", e),
+            ParseErrorKind::MissingTileLayer => write!(f, "the map has no tile layer"),
+            ParseErrorKind::InfiniteTileLayer => {
+                write!(f, "the tile layer must not be infinite")
+            }
+            ParseErrorKind::EmptyTile => {
+                write!(f, "the tile is empty or has no properties")
+            }
+            ParseErrorKind::MissingProperty(property) => {
+                write!(f, "missing property '{}'", property)
+            }
+            ParseErrorKind::WrongPropertyType { property, expected } => {
+                write!(f, "property '{}' should be a {}", property, expected)
+            }
+            ParseErrorKind::InvalidValue {
+                property,
+                value,
+                expected,
+            } => {
+                write!(
+                    f,
+                    "property '{}' has value '{}', expected {}",
+                    property, value, expected
+                )
+            }
+            ParseErrorKind::UnknownTexture { property, name } => {
+                write!(
+                    f,
+                    "property '{}' names unknown texture '{}'",
+                    property, name
+                )
+            }
+            ParseErrorKind::UnknownModel { property, name } => {
+                write!(f, "property '{}' names unknown model '{}'", property, name)
+            }
+            ParseErrorKind::UnknownMover { property, name } => {
+                write!(f, "property '{}' names unknown mover '{}'", property, name)
+            }
+            ParseErrorKind::WrongObjectShape(expected) => {
+                write!(f, "the object should be a {}", expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ParseErrorKind::Io(e) => Some(e),
+            ParseErrorKind::Image(e) => Some(e),
+            ParseErrorKind::Tiled(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Parses the blueprint of the test data with the name, which uses no textures
+/// or models. The test data is a single file for each blueprint.
+#[cfg(test)]
+pub(crate) fn parse_test_blueprint(name: &str) -> Tilemap {
+    let mut texture_set = TextureSet {
+        named: &[],
+        animations: Vec::new(),
+    };
+    let tmx_path = Path::new("src/map/test_data").join(format!("{}.tmx", name));
+    parse_blueprint(
+        &tmx_path,
+        TilemapID(0),
+        name.to_owned(),
+        &mut texture_set,
+        &[],
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn objects_keep_their_custom_properties() {
+        let tilemap = parse_test_blueprint("objects");
+
+        let [spawner] = tilemap.spawner_placeholders.as_slice() else {
+            panic!("expected one spawner: {:?}", tilemap.spawner_placeholders);
+        };
+        assert_eq!(spawner.entity, "crate");
+        assert_eq!(spawner.position, Vec3::new(1.5, 0.5, 1.5));
+        assert_eq!(spawner.yaw, std::f32::consts::FRAC_PI_2);
+        assert_eq!(
+            spawner.properties.get("loot"),
+            Some(&PropertyValue::StringValue("key".to_owned()))
+        );
+
+        let [light] = tilemap.light_placeholders.as_slice() else {
+            panic!("expected one light: {:?}", tilemap.light_placeholders);
+        };
+        assert_eq!(light.intensity, 2.0);
+        assert_eq!(
+            light.properties.get("flicker"),
+            Some(&PropertyValue::BoolValue(true))
+        );
+
+        let [trigger] = tilemap.trigger_placeholders.as_slice() else {
+            panic!("expected one trigger: {:?}", tilemap.trigger_placeholders);
+        };
+        assert_eq!(
+            trigger.properties.get("message"),
+            Some(&PropertyValue::StringValue("hello".to_owned()))
+        );
+    }
 }
@@ -1,4 +1,4 @@
-use glam::{Vec2, Vec3};
+use glam::{Mat2, Vec2, Vec3};
 
 use crate::raycaster::PointXZ;
 
@@ -15,307 +15,242 @@ pub struct Portal {
     pub position: PointXZ<u64>,
     pub center: Vec2,
     pub ground_height: f32,
+    /// Extra rotation of the linked room around the portal, in radians.
+    pub yaw: f32,
+    /// How many times bigger this room is from the side of the linked room.
+    pub scale: f32,
+    /// Height above the ground at which the linked room lines up.
+    pub height_offset: f32,
     pub destination: Option<(RoomID, PortalID)>,
 }
 
-/*impl Portal {
-    /// Returns new position and a difference in angle
-    pub fn teleport_to(&self, origin: Vec3, dest: Portal) -> Vec3 {
-        let offset_x = self.center.x - origin.x;
-        let offset_z = self.center.z - origin.z;
-        let mut new_origin = Vec3::new(
-            dest.center.x,
-            origin.y + dest.ground_height - self.ground_height,
-            dest.center.z,
-        );
-
-        match self.orientation {
-            Orientation::North => match dest.orientation {
-                Orientation::North => {
-                    new_origin.x += offset_x;
-                    new_origin.z += 1.0 + offset_z;
-                }
-                Orientation::South => {
-                    new_origin.x += -offset_x;
-                    new_origin.z += -1.0 - offset_z;
-                }
-                Orientation::East => {
-                    new_origin.x += 1.0 + offset_z;
-                    new_origin.z += -offset_x;
-                }
-                Orientation::West => {
-                    new_origin.x += -1.0 - offset_z;
-                    new_origin.z += offset_x;
-                }
-            },
-            Orientation::South => match dest.orientation {
-                Orientation::North => {
-                    new_origin.x += -offset_x;
-                    new_origin.z += 1.0 - offset_z;
-                }
-                Orientation::South => {
-                    new_origin.x += offset_x;
-                    new_origin.z += -1.0 + offset_z;
-                }
-                Orientation::East => {
-                    new_origin.x += 1.0 - offset_z;
-                    new_origin.z += offset_x;
-                }
-                Orientation::West => {
-                    new_origin.x += -1.0 + offset_z;
-                    new_origin.z += -offset_x;
-                }
-            },
-            Orientation::East => match dest.orientation {
-                Orientation::North => {
-                    new_origin.x += -offset_z;
-                    new_origin.z += 1.0 + offset_x;
-                }
-                Orientation::South => {
-                    new_origin.x += offset_z;
-                    new_origin.z += -1.0 - offset_x;
-                }
-                Orientation::East => {
-                    new_origin.x += 1.0 + offset_x;
-                    new_origin.z += offset_z;
-                }
-                Orientation::West => {
-                    new_origin.x += -1.0 - offset_x;
-                    new_origin.z += -offset_z;
-                }
-            },
-            Orientation::West => match dest.orientation {
-                Orientation::North => {
-                    new_origin.x += offset_z;
-                    new_origin.z += 1.0 - offset_x;
-                }
-                Orientation::South => {
-                    new_origin.x += -offset_z;
-                    new_origin.z += -1.0 + offset_x;
-                }
-                Orientation::East => {
-                    new_origin.x += 1.0 - offset_x;
-                    new_origin.z += -offset_z;
-                }
-                Orientation::West => {
-                    new_origin.x += -1.0 + offset_x;
-                    new_origin.z += offset_z;
-                }
-            },
-        };
-        new_origin
-    }
-
-    pub fn direction_difference(&self, other: &Self) -> Rotation {
-        self.orientation.difference(other.orientation)
+impl Portal {
+    /// Point in the middle of the portal tile side which faces into the room,
+    /// at the height where the linked room lines up.
+    fn anchor(&self) -> Vec3 {
+        let anchor = self.center - self.direction * 0.5;
+        Vec3::new(anchor.x, self.ground_height + self.height_offset, anchor.y)
     }
-}*/
+}
 
+/// Maps positions and directions from the room of the source portal into the
+/// room of the destination portal, as if the rooms were placed side by side.
 #[derive(Debug, Clone, Copy)]
-pub enum Orientation {
-    East = 0,
-    North = 90,
-    West = 180,
-    South = 270,
+pub struct PortalTransform {
+    src_anchor: Vec3,
+    dest_anchor: Vec3,
+    /// Rotation around the y-axis which increases in a counter clockwise direction.
+    yaw: f32,
+    rotation: Mat2,
+    scale: f32,
 }
 
-impl Orientation {
-    pub fn difference(self, other: Self) -> Rotation {
-        match other as i32 - self as i32 {
-            0 => Rotation::Deg0,
-            -90 | 270 => Rotation::AnticlockwiseDeg90,
-            90 | -270 => Rotation::ClockwiseDeg90,
-            180 | -180 => Rotation::Deg180,
-            _ => unreachable!(),
+impl PortalTransform {
+    /// Transform for passing through the `src` portal and coming out of the `dest`
+    /// portal. Passing back from `dest` to `src` is the inverse transform.
+    pub fn new(src: &Portal, dest: &Portal) -> Self {
+        let src_angle = f32::atan2(src.direction.y, src.direction.x);
+        let dest_angle = f32::atan2(-dest.direction.y, -dest.direction.x);
+        let yaw = dest_angle - src_angle + src.yaw - dest.yaw;
+        Self {
+            src_anchor: src.anchor(),
+            dest_anchor: dest.anchor(),
+            yaw,
+            rotation: Mat2::from_angle(yaw),
+            scale: dest.scale / src.scale,
         }
     }
 
-    pub fn from_angle(angle: i32) -> Self {
-        match angle {
-            0 | 360 => Self::East,
-            -90 | 270 => Self::South,
-            90 | -270 => Self::North,
-            180 | -180 => Self::West,
-            _ => unreachable!(),
-        }
+    pub fn position(&self, position: Vec3) -> Vec3 {
+        let offset = (position - self.src_anchor) * self.scale;
+        let horizontal = self.rotation * Vec2::new(offset.x, offset.z);
+        let horizontal = Vec2::new(self.dest_anchor.x, self.dest_anchor.z) + horizontal;
+        Vec3::new(horizontal.x, self.height(position.y), horizontal.y)
     }
-}
 
-#[derive(Debug, Clone, Copy)]
-pub enum Rotation {
-    Deg0 = 0,
-    AnticlockwiseDeg90 = 90,
-    ClockwiseDeg90 = -90,
-    Deg180 = 180,
-}
-
-/*#[test]
-fn teleportation_test() {
-    let mut src = Portal {
-        id: PortalID(0),
-        direction: PortalDirection::North,
-        position: PointXZ::new(2, 3),
-        center: PointXZ::new(2.5, 3.5),
-        ground_level: 2.0,
-        link: None,
-    };
-    let mut dest = Portal {
-        id: PortalID(0),
-        direction: PortalDirection::North,
-        position: PointXZ::new(2, 3),
-        center: PointXZ::new(2.5, 3.5),
-        ground_level: 3.0,
-        link: None,
-    };
-    let origin = Vec3::new(2.2, 0.0, 3.8);
-    dest.direction = PortalDirection::North;
-    assert_eq!(
-        (Vec3::new(2.8, 1.0, 4.2), PI),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+    /// Maps only the `Y-level`, which doesn't depend on the horizontal position.
+    pub fn height(&self, height: f32) -> f32 {
+        self.dest_anchor.y + (height - self.src_anchor.y) * self.scale
+    }
 
-    dest.direction = PortalDirection::South;
-    assert_eq!(
-        (Vec3::new(2.2, 1.0, 2.8), 0.0),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+    /// Rotates the direction, but keeps its length.
+    pub fn direction(&self, direction: Vec3) -> Vec3 {
+        let horizontal = self.rotation * Vec2::new(direction.x, direction.z);
+        Vec3::new(horizontal.x, direction.y, horizontal.y)
+    }
 
-    dest.direction = PortalDirection::East;
-    assert_eq!(
-        (Vec3::new(3.2, 1.0, 3.2), PI * 0.5),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+    pub fn yaw(&self) -> f32 {
+        self.yaw
+    }
 
-    dest.direction = PortalDirection::West;
-    assert_eq!(
-        (Vec3::new(1.8, 1.0, 3.8), -PI * 0.5),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+    /// How many times longer distances get in the destination room.
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+}
 
-    src.direction = PortalDirection::South;
-    let origin = Vec3::new(2.4, 0.0, 3.2);
-    dest.direction = PortalDirection::North;
-    assert_eq!(
-        (Vec3::new(2.4, 1.0, 4.2), 0.0),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    dest.direction = PortalDirection::South;
-    assert_eq!(
-        (Vec3::new(2.6, 1.0, 2.8), PI),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+    fn test_portal(direction: Vec2, x: u64, z: u64, ground_height: f32) -> Portal {
+        Portal {
+            id: PortalID(0),
+            direction,
+            position: PointXZ::new(x, z),
+            center: Vec2::new(x as f32 + 0.5, z as f32 + 0.5),
+            ground_height,
+            yaw: 0.0,
+            scale: 1.0,
+            height_offset: 0.0,
+            destination: None,
+        }
+    }
 
-    dest.direction = PortalDirection::East;
-    assert_eq!(
-        (Vec3::new(3.2, 1.0, 3.6), -PI * 0.5),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+    fn assert_close(expected: Vec3, actual: Vec3, message: &str) {
+        assert!(
+            (expected - actual).length() < 1e-5,
+            "expected {}, got {}: {}",
+            expected,
+            actual,
+            message
+        );
+    }
 
-    dest.direction = PortalDirection::West;
-    assert_eq!(
-        (Vec3::new(1.8, 1.0, 3.4), PI * 0.5),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+    #[test]
+    fn portal_transform_direction_pairs() {
+        let (north, east, south, west) = (Vec2::Y, Vec2::X, Vec2::NEG_Y, Vec2::NEG_X);
+        // Direction of the source portal, position 0.2 inside the source portal
+        // tile and 0.1 to the left of its middle, seen from the room
+        let sources = [
+            (north, "N", Vec3::new(2.4, 1.5, 3.2)),
+            (east, "E", Vec3::new(2.2, 1.5, 3.6)),
+            (south, "S", Vec3::new(2.6, 1.5, 3.8)),
+            (west, "W", Vec3::new(2.8, 1.5, 3.4)),
+        ];
+        // Direction of the destination portal, the same position 0.2 inside the
+        // destination room and the yaw in degrees for each source direction
+        let destinations = [
+            (
+                north,
+                "N",
+                Vec3::new(6.6, 3.5, 0.8),
+                [180.0, 270.0, 0.0, 90.0],
+            ),
+            (
+                east,
+                "E",
+                Vec3::new(5.8, 3.5, 1.4),
+                [90.0, 180.0, 270.0, 0.0],
+            ),
+            (
+                south,
+                "S",
+                Vec3::new(6.4, 3.5, 2.2),
+                [0.0, 90.0, 180.0, 270.0],
+            ),
+            (
+                west,
+                "W",
+                Vec3::new(7.2, 3.5, 1.6),
+                [270.0, 0.0, 90.0, 180.0],
+            ),
+        ];
 
-    src.direction = PortalDirection::East;
-    let origin = Vec3::new(2.8, 0.0, 3.2);
-    dest.direction = PortalDirection::North;
-    assert_eq!(
-        (Vec3::new(2.2, 1.0, 4.2), -PI * 0.5),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+        for (dest_direction, dest_name, expected_position, yaws) in destinations {
+            for ((src_direction, src_name, position), expected_yaw) in
+                sources.into_iter().zip(yaws)
+            {
+                let message = format!("from {} to {}", src_name, dest_name);
+                let src = test_portal(src_direction, 2, 3, 1.0);
+                let dest = test_portal(dest_direction, 6, 1, 3.0);
+                let transform = PortalTransform::new(&src, &dest);
 
-    dest.direction = PortalDirection::South;
-    assert_eq!(
-        (Vec3::new(2.8, 1.0, 2.8), PI * 0.5),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+                assert_close(expected_position, transform.position(position), &message);
+                // Walking into the source portal means walking out of the destination portal
+                assert_close(
+                    Vec3::new(-dest_direction.x, 0.0, -dest_direction.y),
+                    transform.direction(Vec3::new(src_direction.x, 0.0, src_direction.y)),
+                    &message,
+                );
+                let yaw = transform.yaw().to_degrees().rem_euclid(360.0);
+                let yaw_error = (yaw - expected_yaw).abs();
+                assert!(
+                    yaw_error < 1e-3 || (360.0 - yaw_error) < 1e-3,
+                    "expected yaw {}, got {}: {}",
+                    expected_yaw,
+                    yaw,
+                    message
+                );
+            }
+        }
+    }
 
-    dest.direction = PortalDirection::East;
-    assert_eq!(
-        (Vec3::new(3.2, 1.0, 3.8), PI),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+    #[test]
+    fn portal_transform_heights() {
+        let src = test_portal(Vec2::Y, 2, 3, 1.0);
+        let mut dest = test_portal(Vec2::NEG_Y, 2, 1, -2.0);
+        let position = Vec3::new(2.5, 1.5, 3.2);
 
-    dest.direction = PortalDirection::West;
-    assert_eq!(
-        (Vec3::new(1.8, 1.0, 3.2), 0.0),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+        // Height above the ground stays the same
+        let transform = PortalTransform::new(&src, &dest);
+        assert_close(
+            Vec3::new(2.5, -1.5, 2.2),
+            transform.position(position),
+            "lower",
+        );
+        let transform = PortalTransform::new(&dest, &src);
+        assert_close(
+            Vec3::new(2.5, 1.5, 3.2),
+            transform.position(Vec3::new(2.5, -1.5, 2.2)),
+            "higher",
+        );
 
-    src.direction = PortalDirection::West;
-    let origin = Vec3::new(2.2, 0.0, 3.2);
-    dest.direction = PortalDirection::North;
-    assert_eq!(
-        (Vec3::new(2.8, 1.0, 4.2), PI * 0.5),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+        // The destination room lines up higher above its ground
+        dest.height_offset = 0.5;
+        let transform = PortalTransform::new(&src, &dest);
+        assert_close(
+            Vec3::new(2.5, -1.0, 2.2),
+            transform.position(position),
+            "offset",
+        );
 
-    dest.direction = PortalDirection::South;
-    assert_eq!(
-        (Vec3::new(2.2, 1.0, 2.8), -PI * 0.5),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+        // Distances, including heights above the ground, get longer in bigger rooms
+        dest.scale = 2.0;
+        let transform = PortalTransform::new(&src, &dest);
+        assert_close(
+            Vec3::new(2.5, -0.5, 2.4),
+            transform.position(position),
+            "scaled",
+        );
+        assert_eq!(transform.scale(), 2.0);
+    }
 
-    dest.direction = PortalDirection::East;
-    assert_eq!(
-        (Vec3::new(3.2, 1.0, 3.2), 0.0),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+    #[test]
+    fn portal_transform_inverse() {
+        let mut src = test_portal(Vec2::X, 2, 3, 1.0);
+        src.yaw = 30f32.to_radians();
+        src.height_offset = 0.25;
+        let mut dest = test_portal(Vec2::Y, 6, 1, 3.0);
+        dest.scale = 1.5;
+        let position = Vec3::new(2.2, 1.7, 3.4);
+        let direction = Vec3::new(0.6, 0.0, -0.8);
 
-    dest.direction = PortalDirection::West;
-    assert_eq!(
-        (Vec3::new(1.8, 1.0, 3.8), PI),
-        src.teleport(origin, dest),
-        "from: {:?}, to: {:?}",
-        src.direction,
-        dest.direction
-    );
+        let there = PortalTransform::new(&src, &dest);
+        let back = PortalTransform::new(&dest, &src);
+        assert_close(
+            position,
+            back.position(there.position(position)),
+            "position",
+        );
+        assert_close(
+            direction,
+            back.direction(there.direction(direction)),
+            "direction",
+        );
+        // Yaw of the way back turns the view around by a full circle at most
+        let yaw = there.yaw() + back.yaw();
+        assert!(yaw.sin().abs() < 1e-5 && yaw.cos() > 0.0);
+        assert!((there.scale() * back.scale() - 1.0).abs() < 1e-5);
+    }
 }
-*/
@@ -1,4 +1,6 @@
-use glam::Vec2;
+use std::sync::Arc;
+
+use glam::{Vec2, Vec3};
 use rand::{rngs::ThreadRng, seq::SliceRandom, Rng};
 
 use crate::{
@@ -7,13 +9,18 @@ use crate::{
 };
 
 use super::{
-    tilemap::{Tilemap, TilemapID, ObjectID, Skybox},
-    portal::{Orientation, Portal, PortalID, Rotation},
+    lightmap::PointLight,
+    mover::MoverState,
+    portal::{Portal, PortalID},
+    tilemap::{
+        Fog, Object, ObjectID, Skybox, Spawner, Sprite, Tile, Tilemap, TilemapID, Trigger,
+    },
 };
 
 const VOXEL_CHANCE: f64 = 0.3;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// Rooms get new IDs which are never reused, even after the room is unloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct RoomID(pub usize);
 
 // TODO remove 'pub'
@@ -23,10 +30,28 @@ pub struct Room {
     pub(super) tilemap_id: TilemapID,
     // Each portal has its own index which is the position in this Vec
     pub(super) portals: Vec<Portal>,
-    //pub(super) objects: Vec<Option<ModelID>>,
+    pub(super) objects: Vec<Option<Object>>,
     pub(super) is_fully_generated: bool,
+    /// Seed from which the adjacent rooms are generated.
+    pub(super) seed: u64,
+    /// Number of portals between this room and the starting room.
+    pub(super) depth: u32,
+    /// What is left of the links to unloaded rooms, for each portal.
+    pub(super) unloaded_links: Vec<UnloadedLink>,
+    /// The room from which this room was generated, unless this is the starting room.
+    pub(super) origin: Option<Arc<RoomOrigin>>,
     pub(super) skybox: Skybox,
     pub(super) ambient_light_intensity: f32,
+    pub(super) fog: Fog,
+    pub(super) sprites: Vec<Sprite>,
+    /// Tiles of the blueprint with the heights moved by the movers.
+    /// Empty if the blueprint has no movers, so the blueprint tiles are used.
+    pub(super) tiles: Vec<Tile>,
+    /// State of each mover, in the same order as the movers of the blueprint.
+    pub(super) movers: Vec<MoverState>,
+    pub(super) triggers: Vec<Trigger>,
+    pub(super) spawners: Vec<Spawner>,
+    pub(super) lights: Vec<PointLight>,
 
     // TODO finish orientation for skyboxes to remain in place
     /// To which side is the room oriented to or to where points the room north
@@ -34,25 +59,59 @@ pub struct Room {
 }
 
 impl Room {
-    pub fn new(id: RoomID, tilemap: &Tilemap, direction: Vec2) -> Self {
+    pub fn new(
+        id: RoomID,
+        tilemap: &Tilemap,
+        direction: Vec2,
+        seed: u64,
+        depth: u32,
+        origin: Option<Arc<RoomOrigin>>,
+    ) -> Self {
         Self {
             id,
             tilemap_id: tilemap.id,
             portals: tilemap.unlinked_portals.clone(),
-            //objects: blueprint.object_placeholders.clone(),
+            objects: tilemap.object_placeholders.clone(),
             is_fully_generated: false,
+            seed,
+            depth,
+            unloaded_links: vec![UnloadedLink::default(); tilemap.unlinked_portals.len()],
+            origin,
             skybox: tilemap.default_skybox,
             ambient_light_intensity: tilemap.default_ambient_light,
+            fog: tilemap.default_fog,
+            sprites: tilemap.sprite_placeholders.clone(),
+            tiles: if tilemap.movers.is_empty() {
+                Vec::new()
+            } else {
+                tilemap.tiles.clone()
+            },
+            movers: vec![MoverState::default(); tilemap.movers.len()],
+            triggers: tilemap.trigger_placeholders.clone(),
+            spawners: tilemap.spawner_placeholders.clone(),
+            lights: tilemap.light_placeholders.clone(),
 
             direction,
         }
     }
 
-    // TODO show in dbg
-    pub fn get_portals(&self) -> &[Portal] {
-        &self.portals
+    /// Returns the stub from which the same room can be generated again
+    /// behind the portal of another room linked to this room's `portal_id`.
+    pub fn to_stub(&self, portal_id: PortalID) -> RoomStub {
+        RoomStub {
+            tilemap_id: self.tilemap_id,
+            portal_id,
+            seed: self.seed,
+            depth: self.depth,
+            origin: self.origin.clone(),
+        }
+    }
+
+    pub fn id(&self) -> RoomID {
+        self.id
     }
 
+    // TODO show in dbg
     pub fn ambient_light_intensity(&self) -> f32 {
         self.ambient_light_intensity
     }
@@ -60,6 +119,90 @@ impl Room {
     pub fn skybox(&self) -> &Skybox {
         &self.skybox
     }
+
+    pub fn fog(&self) -> Fog {
+        self.fog
+    }
+
+    pub fn sprites(&self) -> &[Sprite] {
+        &self.sprites
+    }
+
+    pub fn triggers(&self) -> &[Trigger] {
+        &self.triggers
+    }
+
+    pub fn spawners(&self) -> &[Spawner] {
+        &self.spawners
+    }
+
+    pub fn lights(&self) -> &[PointLight] {
+        &self.lights
+    }
+
+    /// Moves the movers of the room for the passed time. Movers with a trigger
+    /// tile or a trigger volume at the `player_position` are triggered.
+    pub fn update_movers(
+        &mut self,
+        tilemap: &Tilemap,
+        delta: f32,
+        player_position: Option<Vec3>,
+    ) {
+        let trigger_tile = player_position
+            .and_then(|position| {
+                tilemap.get_tile_checked(
+                    position.x.floor() as i64,
+                    position.z.floor() as i64,
+                )
+            })
+            .and_then(|tile| tile.mover_trigger);
+        let triggers = &self.triggers;
+        let is_triggered = |id: usize| {
+            trigger_tile.is_some_and(|trigger| trigger.0 == id)
+                || player_position.is_some_and(|position| {
+                    triggers.iter().any(|trigger| {
+                        trigger.mover.is_some_and(|mover| mover.0 == id)
+                            && trigger.contains(position)
+                    })
+                })
+        };
+        for (id, (mover, state)) in
+            tilemap.movers.iter().zip(&mut self.movers).enumerate()
+        {
+            state.update(mover, delta, is_triggered(id));
+            mover.apply(state.progress, &tilemap.tiles, &mut self.tiles);
+        }
+    }
+}
+
+/// Remains of the link between a portal and the room behind it
+/// which was unloaded while this room stayed loaded.
+#[derive(Debug, Clone, Default)]
+pub struct UnloadedLink {
+    /// The unloaded room to generate again, if it should be the same one.
+    pub stub: Option<RoomStub>,
+    /// How many times the room behind the portal was forgotten.
+    pub forgotten_count: u64,
+}
+
+/// Everything needed to generate the same unloaded room again.
+#[derive(Debug, Clone)]
+pub struct RoomStub {
+    pub tilemap_id: TilemapID,
+    /// Portal of the unloaded room which was linked to this room.
+    pub portal_id: PortalID,
+    pub seed: u64,
+    pub depth: u32,
+    pub origin: Option<Arc<RoomOrigin>>,
+}
+
+/// The room from which a room was generated. Rooms generated again from
+/// stubs keep the origin, so they can lead back to the same rooms too.
+#[derive(Debug)]
+pub struct RoomOrigin {
+    /// Portal of the generated room which leads to the origin room.
+    pub portal_id: PortalID,
+    pub room: RoomStub,
 }
 
 #[derive(Debug)]
@@ -73,7 +216,52 @@ impl<'a> RoomRef<'a> {
         self.data.portals[local_id.0]
     }
 
-    //pub fn get_object(&self, local_id: ObjectID) -> Option<ModelID> {
-    //    self.data.objects[local_id.0]
-    //}
+    pub fn get_object(&self, local_id: ObjectID) -> Option<Object> {
+        self.data.objects[local_id.0]
+    }
+
+    /// Returns the tile of the room at the provided map coordinates, with the
+    /// current heights. Returns `None` if coordinates are out of bounds.
+    #[inline]
+    pub fn get_tile_checked(&self, x: i64, z: i64) -> Option<&Tile> {
+        let (width, depth) = self.tilemap.dimensions_i64();
+        if x >= width || x < 0 || z >= depth || z < 0 {
+            return None;
+        }
+        self.tiles().get((z * width + x) as usize)
+    }
+
+    pub fn get_tile_unchecked(&self, x: usize, z: usize) -> &Tile {
+        &self.tiles()[z * self.tilemap.dimensions.0 as usize + x]
+    }
+
+    /// Returns the tiles of the room, which are the blueprint tiles
+    /// unless the room has movers.
+    #[inline]
+    fn tiles(&self) -> &'a [Tile] {
+        if self.tilemap.movers.is_empty() {
+            &self.tilemap.tiles
+        } else {
+            &self.data.tiles
+        }
+    }
+
+    /// Returns the span of the tile in which the `y` level is, out of the
+    /// tile and the spans stacked on it. That is the one with the highest
+    /// bottom below `y`, or the tile if all of them are above `y`.
+    pub fn get_span_checked(&self, x: i64, z: i64, y: f32) -> Option<&Tile> {
+        let tile = self.get_tile_checked(x, z)?;
+        let span = self
+            .tilemap
+            .get_spans_unchecked(x as usize, z as usize)
+            .filter(|span| span.bottom_height <= y)
+            .fold(tile, |best, span| {
+                if best.bottom_height > y || span.bottom_height > best.bottom_height {
+                    span
+                } else {
+                    best
+                }
+            });
+        Some(span)
+    }
 }
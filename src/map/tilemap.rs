@@ -1,6 +1,14 @@
+use glam::{Mat2, Vec2, Vec3};
+use tiled::Properties;
+
 use crate::{models::ModelID, raycaster::PointXZ, textures::TextureID};
 
-use super::portal::{ Portal, PortalID};
+use super::{
+    generator::GenerationRules,
+    lightmap::{side_index, PointLight, TileLight},
+    mover::{Mover, MoverID},
+    portal::{Portal, PortalID, PortalTransform},
+};
 
 #[derive(Debug, Clone, Copy)]
 pub struct TilemapID(pub usize);
@@ -12,15 +20,40 @@ pub struct TilemapID(pub usize);
 #[derive(Debug)]
 pub struct Tilemap {
     pub(super) id: TilemapID,
+    /// Name of the blueprint directory.
+    pub(super) name: String,
     pub(super) dimensions: (u64, u64),
     pub(super) tiles: Vec<Tile>,
+    /// Tiles of each tile layer after the first one, in the same order as
+    /// `tiles`. They stack more height spans on the tiles, like bridges above
+    /// the ground, but don't move and have no portals or objects.
+    pub(super) span_layers: Vec<Vec<Option<Tile>>>,
     // TODO is unlinked portals a good name?????
     pub(super) unlinked_portals: Vec<Portal>,
-    //pub(super) object_placeholders: Vec<Option<ModelID>>,
+    // Each object has its own index which is the position in this Vec
+    pub(super) object_placeholders: Vec<Option<Object>>,
     pub(super) default_skybox: Skybox,
-    // TODO is this needed?????
+    /// If new rooms can be generated from this blueprint.
     pub(super) repeatable: bool,
+    pub(super) generation_rules: GenerationRules,
     pub(super) default_ambient_light: f32,
+    pub(super) default_fog: Fog,
+    /// Baked light of each tile, in the same order as `tiles`. Light is baked
+    /// once at the closed heights, so it doesn't follow the moved tiles.
+    pub(super) lightmap: Vec<TileLight>,
+    /// Baked light of each span, in the same order as `span_layers`.
+    pub(super) span_lightmaps: Vec<Vec<TileLight>>,
+    /// Sprites placed in the blueprint which each [`Room`](super::room::Room)
+    /// gets its own copy of.
+    pub(super) sprite_placeholders: Vec<Sprite>,
+    /// Each mover has its own index which is the position in this Vec.
+    pub(super) movers: Vec<Mover>,
+    /// Where the player starts if this is the starting blueprint.
+    pub(super) spawn: Option<Spawn>,
+    pub(super) trigger_placeholders: Vec<Trigger>,
+    pub(super) spawner_placeholders: Vec<Spawner>,
+    /// Lights placed in the blueprint, already baked into the `lightmap`.
+    pub(super) light_placeholders: Vec<PointLight>,
 }
 
 impl Tilemap {
@@ -54,24 +87,60 @@ impl Tilemap {
         &self.tiles[z * self.dimensions.0 as usize + x]
     }
 
+    /// Returns the baked light of the tile at the provided map coordinates.
+    /// Parsed arguments are assumed to be in map bound and correct.
+    #[inline]
+    pub fn get_tile_light_unchecked(&self, x: usize, z: usize) -> &TileLight {
+        &self.lightmap[z * self.dimensions.0 as usize + x]
+    }
+
+    /// Returns the spans stacked on the tile by the other tile layers.
+    /// Parsed arguments are assumed to be in map bound and correct.
+    pub fn get_spans_unchecked(&self, x: usize, z: usize) -> impl Iterator<Item = &Tile> {
+        let index = z * self.dimensions.0 as usize + x;
+        self.span_layers
+            .iter()
+            .filter_map(move |layer| layer[index].as_ref())
+    }
+
+    /// Returns the spans stacked on the tile together with their baked light.
+    /// Parsed arguments are assumed to be in map bound and correct.
+    pub fn get_lit_spans_unchecked(
+        &self,
+        x: usize,
+        z: usize,
+    ) -> impl Iterator<Item = (&Tile, &TileLight)> {
+        let index = z * self.dimensions.0 as usize + x;
+        self.span_layers
+            .iter()
+            .zip(&self.span_lightmaps)
+            .filter_map(move |(layer, lights)| {
+                Some((layer[index].as_ref()?, &lights[index]))
+            })
+    }
+
     pub fn dimensions_i64(&self) -> (i64, i64) {
         (self.dimensions.0 as i64, self.dimensions.1 as i64)
     }
 }
 
 // TODO maybe rename all with 'level' to 'height'
-// TODO try removing Clone and Copy
-#[derive(Debug, Clone, Copy)]
+// TODO try removing Clone
+#[derive(Debug, Clone)]
 pub struct Tile {
     pub position: PointXZ<u64>,
-    /// Texture of the bottom wall.
-    pub bottom_wall_tex: TextureID,
-    /// Texture of the top wall.
-    pub top_wall_tex: TextureID,
+    /// Textures of the bottom wall sides, in the order: north, east, south, west.
+    pub bottom_wall_texs: [TextureID; 4],
+    /// Textures of the top wall sides, in the same order as the bottom wall.
+    pub top_wall_texs: [TextureID; 4],
     /// Texture of the bottom platform.
     pub ground_tex: TextureID,
     /// Texture of the top platform.
     pub ceiling_tex: TextureID,
+    pub bottom_wall_mapping: TextureMapping,
+    pub top_wall_mapping: TextureMapping,
+    pub ground_mapping: TextureMapping,
+    pub ceiling_mapping: TextureMapping,
     /// `Y-level` - starting lower bound of the bottom wall;
     /// level from which the bottom wall stretches.
     pub bottom_height: f32,
@@ -85,13 +154,253 @@ pub struct Tile {
     pub top_height: f32,
     /// If the current tile should be a portal to different blueprint (map).
     pub portal_id: Option<PortalID>,
+    /// Incline of the ground, which rises from the `ground_height`.
+    pub ground_slope: Slope,
+    /// Incline of the ceiling, which rises from the `ceiling_height`.
+    pub ceiling_slope: Slope,
+    /// Mover which opens when the player stands on this tile.
+    pub mover_trigger: Option<MoverID>,
+    /// Voxel models which cover the tile, drawn when the ray passes over it.
+    pub objects: Vec<ObjectID>,
+}
+
+impl Tile {
+    /// Returns the `Y-level` of the ground at the map coordinates. Coordinates
+    /// outside of the tile give the height of the extended ground plane.
+    pub fn ground_height_at(&self, x: f32, z: f32) -> f32 {
+        self.platforms().ground_height_at(x, z)
+    }
+
+    /// Returns the `Y-level` of the ceiling at the map coordinates. Coordinates
+    /// outside of the tile give the height of the extended ceiling plane.
+    pub fn ceiling_height_at(&self, x: f32, z: f32) -> f32 {
+        self.platforms().ceiling_height_at(x, z)
+    }
+
+    /// Returns the platforms of the tile without the rest of its data.
+    pub fn platforms(&self) -> TilePlatforms {
+        TilePlatforms {
+            position: self.position,
+            ground_height: self.ground_height,
+            ceiling_height: self.ceiling_height,
+            ground_slope: self.ground_slope,
+            ceiling_slope: self.ceiling_slope,
+        }
+    }
+
+    /// Returns the texture of the bottom wall side with the provided normal.
+    #[inline]
+    pub fn bottom_wall_tex(&self, normal: Vec3) -> TextureID {
+        self.bottom_wall_texs[side_index(normal)]
+    }
+
+    /// Returns the texture of the top wall side with the provided normal.
+    #[inline]
+    pub fn top_wall_tex(&self, normal: Vec3) -> TextureID {
+        self.top_wall_texs[side_index(normal)]
+    }
+
+    /// Returns the textures of all faces, the bottom wall sides first, then
+    /// the top wall sides, the ground and the ceiling.
+    pub fn textures(&self) -> impl Iterator<Item = TextureID> {
+        self.bottom_wall_texs
+            .into_iter()
+            .chain(self.top_wall_texs)
+            .chain([self.ground_tex, self.ceiling_tex])
+    }
+
+    /// Returns the textures of all faces in the same order as [`Tile::textures`].
+    pub fn textures_mut(&mut self) -> impl Iterator<Item = &mut TextureID> {
+        self.bottom_wall_texs
+            .iter_mut()
+            .chain(&mut self.top_wall_texs)
+            .chain([&mut self.ground_tex, &mut self.ceiling_tex])
+    }
+}
+
+/// Ground and ceiling of a tile, which is all the bodies collide with.
+#[derive(Debug, Clone, Copy)]
+pub struct TilePlatforms {
+    pub position: PointXZ<u64>,
+    pub ground_height: f32,
+    pub ceiling_height: f32,
+    pub ground_slope: Slope,
+    pub ceiling_slope: Slope,
+}
+
+impl TilePlatforms {
+    /// Same as [`Tile::ground_height_at`].
+    pub fn ground_height_at(&self, x: f32, z: f32) -> f32 {
+        self.ground_height + self.ground_slope.height_at(self.offset_of(x, z))
+    }
+
+    /// Same as [`Tile::ceiling_height_at`].
+    pub fn ceiling_height_at(&self, x: f32, z: f32) -> f32 {
+        self.ceiling_height + self.ceiling_slope.height_at(self.offset_of(x, z))
+    }
+
+    /// Converts the platforms of a tile behind a portal into the units of the
+    /// room in front of it, as if they were on the tile at the `position`.
+    /// The `back` transform passes from the room behind the portal.
+    pub fn through_portal(&self, back: &PortalTransform, position: PointXZ<u64>) -> Self {
+        // Rise per unit moved is the same at any scale, only its direction turns
+        let turn = |slope: Slope| {
+            let gradient =
+                back.direction(Vec3::new(slope.gradient.x, 0.0, slope.gradient.y));
+            Slope {
+                gradient: Vec2::new(gradient.x, gradient.z),
+            }
+        };
+        Self {
+            position,
+            ground_height: back.height(self.ground_height),
+            ceiling_height: back.height(self.ceiling_height),
+            ground_slope: turn(self.ground_slope),
+            ceiling_slope: turn(self.ceiling_slope),
+        }
+    }
+
+    fn offset_of(&self, x: f32, z: f32) -> Vec2 {
+        Vec2::new(x - self.position.x as f32, z - self.position.z as f32)
+    }
+}
+
+/// Incline of a platform which rises from one side of the tile to the opposite one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Slope {
+    /// Rise of the platform per unit moved along the x and z axis.
+    pub gradient: Vec2,
+}
+
+impl Slope {
+    /// Platform which rises by `rise` towards the `direction`, which
+    /// is one of the axis directions.
+    pub fn new(direction: Vec2, rise: f32) -> Self {
+        Self {
+            gradient: direction * rise,
+        }
+    }
 
-    pub object: Option<ObjectID>,
+    pub fn is_flat(&self) -> bool {
+        self.gradient == Vec2::ZERO
+    }
+
+    /// Returns the height above the lowest side of the platform
+    /// at the offset from the tile corner with the lowest coordinates.
+    pub fn height_at(&self, offset: Vec2) -> f32 {
+        // The lowest side is the one with the highest coordinate for falling gradients
+        let from_lowest =
+            Vec2::select(self.gradient.cmplt(Vec2::ZERO), offset - 1.0, offset);
+        self.gradient.dot(from_lowest)
+    }
+
+    /// Returns the normal of the platform if it's a floor.
+    pub fn floor_normal(&self) -> Vec3 {
+        Vec3::new(-self.gradient.x, 1.0, -self.gradient.y).normalize()
+    }
 }
 
+/// How a texture is laid on a tile face. The texture covers a whole unit of
+/// the face texture coordinates, which are first scaled and rotated, then offset.
 #[derive(Debug, Clone, Copy)]
+pub struct TextureMapping {
+    /// Scale and rotation of the texture coordinates.
+    pub matrix: Mat2,
+    pub offset: Vec2,
+}
+
+impl TextureMapping {
+    /// Makes the texture `scale` times bigger, turns it counterclockwise by
+    /// `rotation` radians and then moves it by `offset` along its own axes,
+    /// where the whole texture is one unit long.
+    pub fn new(offset: Vec2, scale: f32, rotation: f32) -> Self {
+        Self {
+            matrix: Mat2::from_angle(-rotation) * scale.recip(),
+            offset,
+        }
+    }
+
+    /// Returns the texture coordinates of the face texture coordinates `uv`.
+    #[inline]
+    pub fn apply(&self, uv: Vec2) -> Vec2 {
+        self.matrix * uv - self.offset
+    }
+}
+
+impl Default for TextureMapping {
+    fn default() -> Self {
+        Self {
+            matrix: Mat2::IDENTITY,
+            offset: Vec2::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct ObjectID(pub usize);
 
+/// A voxel model placed in the room. Every tile the model covers
+/// holds the [`ObjectID`] of the model, along with the other models over it.
+#[derive(Debug, Clone, Copy)]
+pub struct Object {
+    pub model: ModelID,
+    /// Corner of the model with the lowest coordinates.
+    pub position: Vec3,
+    /// Width of a single voxel in tiles.
+    pub voxel_size: f32,
+}
+
+/// A camera-facing 2D image placed anywhere in the room.
+#[derive(Debug, Clone, Copy)]
+pub struct Sprite {
+    /// Position of the bottom center of the sprite.
+    pub position: Vec3,
+    /// Width of the sprite in tiles.
+    pub width: f32,
+    /// Height of the sprite in tiles.
+    pub height: f32,
+    pub texture: TextureID,
+}
+
+/// Where the player starts in the starting room.
+#[derive(Debug, Clone, Copy)]
+pub struct Spawn {
+    /// Position of the player feet.
+    pub position: Vec3,
+    /// Yaw of the view in radians.
+    pub yaw: f32,
+}
+
+/// Box shaped volume which reacts to the player inside of it.
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    /// Corner of the volume with the lowest coordinates.
+    pub min: Vec3,
+    /// Corner of the volume with the highest coordinates.
+    pub max: Vec3,
+    /// Mover which opens while the player is inside of the volume.
+    pub mover: Option<MoverID>,
+    /// Custom properties of the Tiled object.
+    pub properties: Properties,
+}
+
+impl Trigger {
+    pub fn contains(&self, point: Vec3) -> bool {
+        point.cmpge(self.min).all() && point.cmplt(self.max).all()
+    }
+}
+
+/// Place where an entity of the named kind appears in the room.
+#[derive(Debug, Clone)]
+pub struct Spawner {
+    pub entity: String,
+    pub position: Vec3,
+    /// Yaw of the entity in radians.
+    pub yaw: f32,
+    /// Custom properties of the Tiled object.
+    pub properties: Properties,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Skybox {
     pub north: TextureID,
@@ -101,3 +410,23 @@ pub struct Skybox {
     pub top: TextureID,
     pub bottom: TextureID,
 }
+
+/// Depth fog which blends the surfaces towards its color the further they are.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Fog {
+    /// Linear RGB color with each channel in range [0.0, 1.0].
+    pub color: Vec3,
+    /// Distance in tiles from which the fog starts to appear.
+    pub start: f32,
+    /// How quickly the fog thickens after the start distance.
+    pub density: f32,
+}
+
+impl Fog {
+    /// Returns how much of the fog color covers a surface at the provided
+    /// distance, in range [0.0, 1.0].
+    #[inline]
+    pub fn factor(&self, distance: f32) -> f32 {
+        1.0 - (-self.density * (distance - self.start).max(0.0)).exp()
+    }
+}
@@ -0,0 +1,416 @@
+use std::fmt;
+
+use super::{
+    portal::Portal,
+    tilemap::{Tile, Tilemap},
+};
+use crate::textures::{TextureAnimation, TextureData, TextureID};
+
+/// Height of the player body in `Y-level` units.
+const PLAYER_HEIGHT: f32 = 2.0;
+/// Highest ledge the player can walk onto, the same as the step height
+/// of the player body, and how far its head can reach into a lower ceiling.
+const MAX_STEP_HEIGHT: f32 = 0.4;
+/// Largest difference between portal openings which still line up.
+const PORTAL_OPENING_EPSILON: f32 = 0.001;
+
+/// Checks the parsed blueprints for problems which the parser can't see on
+/// its own, like unreachable tiles or portals which can't be connected.
+pub fn validate(
+    tilemaps: &[Tilemap],
+    textures: &[TextureData],
+    animations: &[TextureAnimation],
+) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    for tilemap in tilemaps {
+        validate_tilemap(tilemap, tilemaps, &mut issues);
+    }
+
+    // Textures are referenced with their index + 1, since the
+    // index 0 is taken by the default texture, and the animations follow them
+    let mut used_textures = vec![false; textures.len() + 1 + animations.len()];
+    let mut mark_used = |id: TextureID| used_textures[id.0] = true;
+    for tilemap in tilemaps {
+        let spans = tilemap.span_layers.iter().flatten().flatten();
+        for tile in tilemap.tiles.iter().chain(spans) {
+            for id in tile.textures() {
+                mark_used(id);
+            }
+        }
+        let skybox = tilemap.default_skybox;
+        for id in [
+            skybox.north,
+            skybox.east,
+            skybox.south,
+            skybox.west,
+            skybox.top,
+            skybox.bottom,
+        ] {
+            mark_used(id);
+        }
+        for sprite in &tilemap.sprite_placeholders {
+            mark_used(sprite.texture);
+        }
+    }
+    // Animations only show textures with lower IDs,
+    // so the used ones are all found going backwards
+    for (index, animation) in animations.iter().enumerate().rev() {
+        if !used_textures[textures.len() + 1 + index] {
+            continue;
+        }
+        match animation {
+            TextureAnimation::Frames(frames) => {
+                for (id, _) in frames {
+                    used_textures[id.0] = true;
+                }
+            }
+            TextureAnimation::Strip { texture, .. } => used_textures[texture.0] = true,
+        }
+    }
+    for (texture, _) in textures
+        .iter()
+        .zip(&used_textures[1..])
+        .filter(|(_, &is_used)| !is_used)
+    {
+        issues.push(Issue {
+            blueprint: None,
+            tile: None,
+            kind: IssueKind::UnusedTexture(texture.name().to_owned()),
+        });
+    }
+
+    issues
+}
+
+fn validate_tilemap(tilemap: &Tilemap, tilemaps: &[Tilemap], issues: &mut Vec<Issue>) {
+    let (width, depth) = tilemap.dimensions_i64();
+    let mut push = |tile: Option<&Tile>, kind: IssueKind| {
+        issues.push(Issue {
+            blueprint: Some(tilemap.name.clone()),
+            // Same coordinates as shown in Tiled, where the y-axis points down
+            tile: tile.map(|tile| (tile.position.x, depth as u64 - tile.position.z - 1)),
+            kind,
+        })
+    };
+
+    if tilemap.unlinked_portals.is_empty() {
+        push(None, IssueKind::NoPortals);
+    }
+
+    // The levels of every tile, the span layers included, follow each other upwards
+    let spans = tilemap.span_layers.iter().flatten().flatten();
+    for tile in tilemap.tiles.iter().chain(spans) {
+        for kind in inverted_heights(tile) {
+            push(Some(tile), kind);
+        }
+    }
+
+    for portal in &tilemap.unlinked_portals {
+        let (x, z) = (portal.position.x as i64, portal.position.z as i64);
+        let tile = tilemap.get_tile_unchecked(x as usize, z as usize);
+        if x == 0 || z == 0 || x == width - 1 || z == depth - 1 {
+            push(Some(tile), IssueKind::PortalOnBorder);
+        }
+
+        // The view through the portal is shifted so the ground heights of both
+        // portals match. The ceilings then match for the same openings, or else
+        // the player has to fit through both of them.
+        let opening = portal_opening(tile, portal);
+        let has_partner = tilemaps.iter().any(|other| {
+            other.unlinked_portals.iter().any(|other_portal| {
+                let is_same =
+                    other.id.0 == tilemap.id.0 && other_portal.id.0 == portal.id.0;
+                let other_tile = other.get_tile_unchecked(
+                    other_portal.position.x as usize,
+                    other_portal.position.z as usize,
+                );
+                let other_opening = portal_opening(other_tile, other_portal);
+                !is_same
+                    && ((other_opening - opening).abs() <= PORTAL_OPENING_EPSILON
+                        || (fits_player(opening) && fits_player(other_opening)))
+            })
+        });
+        if !has_partner {
+            push(Some(tile), IssueKind::UnmatchedPortal { opening });
+        }
+    }
+
+    // Movers can open the way to more tiles, so the tiles
+    // can be walked both with closed and fully open movers
+    let mut open_tiles = tilemap.tiles.clone();
+    for mover in &tilemap.movers {
+        mover.apply(1.0, &tilemap.tiles, &mut open_tiles);
+    }
+
+    // Walk from all portals, since the player can enter the room through any of them
+    let mut is_reached = vec![false; tilemap.tiles.len()];
+    let mut stack: Vec<(i64, i64)> = tilemap
+        .unlinked_portals
+        .iter()
+        .map(|portal| (portal.position.x as i64, portal.position.z as i64))
+        .collect();
+    for &(x, z) in &stack {
+        is_reached[(z * width + x) as usize] = true;
+    }
+    while let Some((x, z)) = stack.pop() {
+        let from = tilemap.get_tile_unchecked(x as usize, z as usize);
+        for (next_x, next_z) in [(x + 1, z), (x - 1, z), (x, z + 1), (x, z - 1)] {
+            let Some(to) = tilemap.get_tile_checked(next_x, next_z) else {
+                continue;
+            };
+            let index = (next_z * width + next_x) as usize;
+            let from_index = (z * width + x) as usize;
+            if !is_reached[index]
+                && (can_walk(from, to)
+                    || can_walk(&open_tiles[from_index], &open_tiles[index]))
+            {
+                is_reached[index] = true;
+                stack.push((next_x, next_z));
+            }
+        }
+    }
+    for ((tile, _), _) in tilemap
+        .tiles
+        .iter()
+        .zip(&open_tiles)
+        .zip(is_reached)
+        .filter(|((tile, open_tile), is_reached)| {
+            !is_reached && (can_stand(tile) || can_stand(open_tile))
+        })
+    {
+        push(Some(tile), IssueKind::UnreachableTile);
+    }
+}
+
+/// Returns an issue for each pair of neighbouring levels of the tile
+/// where the lower one is above the upper one.
+fn inverted_heights(tile: &Tile) -> impl Iterator<Item = IssueKind> {
+    let levels = [
+        (Level::Bottom, tile.bottom_height),
+        (Level::Ground, tile.ground_height),
+        (Level::Ceiling, tile.ceiling_height),
+        (Level::Top, tile.top_height),
+    ];
+    (0..levels.len() - 1).filter_map(move |index| {
+        let ((lower, lower_height), (upper, upper_height)) =
+            (levels[index], levels[index + 1]);
+        (lower_height > upper_height).then_some(IssueKind::InvertedHeights {
+            lower,
+            lower_height,
+            upper,
+            upper_height,
+        })
+    })
+}
+
+/// Height between the ground and the ceiling of the portal tile,
+/// in the units of the room on the other side of the portal.
+fn portal_opening(tile: &Tile, portal: &Portal) -> f32 {
+    (tile.ceiling_height - tile.ground_height) / portal.scale
+}
+
+/// Whether the player can walk through an opening of the height.
+fn fits_player(opening: f32) -> bool {
+    opening + MAX_STEP_HEIGHT >= PLAYER_HEIGHT
+}
+
+fn can_stand(tile: &Tile) -> bool {
+    let x = tile.position.x as f32 + 0.5;
+    let z = tile.position.z as f32 + 0.5;
+    tile.ceiling_height_at(x, z) + MAX_STEP_HEIGHT
+        >= tile.ground_height_at(x, z) + PLAYER_HEIGHT
+}
+
+/// Whether the player can walk from one tile onto the neighbouring one.
+/// Falling down is allowed, but climbing only up to the step height.
+/// Sloped platforms are compared in the middle of the shared edge.
+fn can_walk(from: &Tile, to: &Tile) -> bool {
+    let x = (from.position.x + to.position.x) as f32 * 0.5 + 0.5;
+    let z = (from.position.z + to.position.z) as f32 * 0.5 + 0.5;
+    let (from_ground, to_ground) =
+        (from.ground_height_at(x, z), to.ground_height_at(x, z));
+    let feet = from_ground.max(to_ground);
+    to_ground - from_ground <= MAX_STEP_HEIGHT
+        && to.ceiling_height_at(x, z) + MAX_STEP_HEIGHT >= feet + PLAYER_HEIGHT
+}
+
+/// Problem found in the parsed map data.
+#[derive(Debug)]
+pub struct Issue {
+    /// Name of the blueprint in which the problem was found, if any.
+    pub blueprint: Option<String>,
+    /// Coordinates of the tile, the same as shown in Tiled.
+    pub tile: Option<(u64, u64)>,
+    pub kind: IssueKind,
+}
+
+impl Issue {
+    pub fn severity(&self) -> Severity {
+        match self.kind {
+            IssueKind::InvertedHeights { .. }
+            | IssueKind::PortalOnBorder
+            | IssueKind::UnmatchedPortal { .. }
+            | IssueKind::NoPortals => Severity::Error,
+            IssueKind::UnreachableTile | IssueKind::UnusedTexture(_) => Severity::Warning,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// One of the `Y-levels` of a tile, from the lowest to the highest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Bottom,
+    Ground,
+    Ceiling,
+    Top,
+}
+
+#[derive(Debug)]
+pub enum IssueKind {
+    /// A level of the tile is above the level which should be over it.
+    InvertedHeights {
+        lower: Level,
+        lower_height: f32,
+        upper: Level,
+        upper_height: f32,
+    },
+    /// The player can stand on the tile, but can't walk to it from any portal.
+    UnreachableTile,
+    PortalOnBorder,
+    /// No other portal has the same height between the ground and the ceiling,
+    /// and the player can't fit through this portal or any other.
+    UnmatchedPortal {
+        opening: f32,
+    },
+    /// The blueprint can't be connected to other rooms.
+    NoPortals,
+    /// The texture is not used by any blueprint.
+    UnusedTexture(String),
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Bottom => write!(f, "bottom"),
+            Level::Ground => write!(f, "ground"),
+            Level::Ceiling => write!(f, "ceiling"),
+            Level::Top => write!(f, "top"),
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity() {
+            Severity::Warning => write!(f, "warning: ")?,
+            Severity::Error => write!(f, "error: ")?,
+        }
+        if let Some(blueprint) = &self.blueprint {
+            write!(f, "blueprint '{}', ", blueprint)?;
+        }
+        if let Some((x, y)) = self.tile {
+            write!(f, "tile at ({}, {}), ", x, y)?;
+        }
+        match &self.kind {
+            IssueKind::InvertedHeights {
+                lower,
+                lower_height,
+                upper,
+                upper_height,
+            } => write!(
+                f,
+                "{} {} is above the {} {}",
+                lower, lower_height, upper, upper_height
+            ),
+            IssueKind::UnreachableTile => {
+                write!(f, "tile can't be reached from any portal")
+            }
+            IssueKind::PortalOnBorder => write!(f, "portal lies on the map border"),
+            IssueKind::UnmatchedPortal { opening } => write!(
+                f,
+                "no other portal lines up with the opening height of {}",
+                opening
+            ),
+            IssueKind::NoPortals => write!(f, "blueprint has no portals"),
+            IssueKind::UnusedTexture(name) => {
+                write!(f, "texture '{}' is never used", name)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::tilemap::{Slope, TextureMapping};
+    use crate::raycaster::PointXZ;
+
+    fn test_tile(bottom: f32, ground: f32, ceiling: f32, top: f32) -> Tile {
+        Tile {
+            position: PointXZ::new(0, 0),
+            bottom_wall_texs: [TextureID(0); 4],
+            top_wall_texs: [TextureID(0); 4],
+            ground_tex: TextureID(0),
+            ceiling_tex: TextureID(0),
+            bottom_wall_mapping: TextureMapping::default(),
+            top_wall_mapping: TextureMapping::default(),
+            ground_mapping: TextureMapping::default(),
+            ceiling_mapping: TextureMapping::default(),
+            bottom_height: bottom,
+            ground_height: ground,
+            ceiling_height: ceiling,
+            top_height: top,
+            portal_id: None,
+            ground_slope: Slope::default(),
+            ceiling_slope: Slope::default(),
+            mover_trigger: None,
+            objects: Vec::new(),
+        }
+    }
+
+    fn inverted_pairs(tile: &Tile) -> Vec<(Level, Level)> {
+        inverted_heights(tile)
+            .map(|kind| match kind {
+                IssueKind::InvertedHeights { lower, upper, .. } => (lower, upper),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inverted_heights_are_found_for_each_pair() {
+        assert!(inverted_pairs(&test_tile(-1.0, 0.0, 4.0, 5.0)).is_empty());
+        // Walls of zero height are allowed
+        assert!(inverted_pairs(&test_tile(0.0, 0.0, 4.0, 4.0)).is_empty());
+        assert_eq!(
+            inverted_pairs(&test_tile(1.0, 0.0, 4.0, 5.0)),
+            [(Level::Bottom, Level::Ground)]
+        );
+        assert_eq!(
+            inverted_pairs(&test_tile(-1.0, 4.5, 4.0, 5.0)),
+            [(Level::Ground, Level::Ceiling)]
+        );
+        assert_eq!(
+            inverted_pairs(&test_tile(-1.0, 0.0, 4.0, 3.0)),
+            [(Level::Ceiling, Level::Top)]
+        );
+    }
+
+    #[test]
+    fn shipped_maps_have_no_errors() {
+        let (tilemaps, textures, animations, _) =
+            crate::map::parser::parse("tiled").unwrap();
+        let errors: Vec<String> = validate(&tilemaps, &textures, &animations)
+            .iter()
+            .filter(|issue| issue.severity() == Severity::Error)
+            .map(|issue| issue.to_string())
+            .collect();
+        assert!(errors.is_empty(), "{:#?}", errors);
+    }
+}
@@ -1,4 +1,9 @@
-use dot_vox::{Color, Model};
+use dot_vox::{Color, DotVoxData, SceneNode};
+use glam::{IVec3, Mat3, UVec3, Vec3};
+
+/// How many `Y-level` units a voxel is high for each tile of its width,
+/// so the voxels look like cubes.
+pub const VOXEL_HEIGHT_SCALE: f32 = 2.0;
 
 const BLANK: Color = Color {
     r: 0,
@@ -16,65 +21,320 @@ impl ModelArray {
         Self { models }
     }
 
-    pub(super) fn get_model_data(&self, id: ModelID) -> ModelDataRef {
+    pub(super) fn get_model_data(&self, id: ModelID) -> ModelDataRef<'_> {
         self.models[id.0].as_ref()
     }
 }
 
+/// Voxels of a model in a box with the y-axis pointing upwards.
 #[derive(Debug)]
 pub struct ModelData {
-    dimension: u32,
+    /// Size on the x-axis.
+    width: u32,
+    /// Size on the y-axis.
+    height: u32,
+    /// Size on the z-axis.
+    depth: u32,
     voxels: Vec<Color>,
 }
 
 impl ModelData {
-    pub fn from_vox_model(model: Model, palette: Vec<Color>) -> Self {
-        assert!(
-            model.size.x == model.size.y
-                && model.size.y == model.size.z
-                && model.size.x == model.size.z,
-            "Dimensions of a voxel not equal!!!"
-        );
-        let dimension = model.size.x;
-        let mut voxels = vec![BLANK; (dimension * dimension * dimension) as usize];
-        model.voxels.iter().for_each(|v| {
-            // Replace y and z since vox models have z axis pointing up
-            let index = position_to_index(dimension, v.x as u32, v.z as u32, v.y as u32);
-            voxels[index] = palette[v.i as usize];
-        });
+    /// Returns the model of the voxels, which are given in the vox coordinates,
+    /// and the corner of the model with the lowest coordinates.
+    fn from_voxels(voxels: &[(IVec3, Color)]) -> (IVec3, Self) {
+        if voxels.is_empty() {
+            let empty = Self {
+                width: 0,
+                height: 0,
+                depth: 0,
+                voxels: Vec::new(),
+            };
+            return (IVec3::ZERO, empty);
+        }
 
-        Self {
-            dimension: model.size.x,
-            voxels,
+        let min = voxels
+            .iter()
+            .fold(IVec3::MAX, |min, (position, _)| min.min(*position));
+        let max = voxels
+            .iter()
+            .fold(IVec3::MIN, |max, (position, _)| max.max(*position));
+        let size = (max - min + 1).as_uvec3();
+
+        // Replace y and z since vox models have z axis pointing up
+        let (width, height, depth) = (size.x, size.z, size.y);
+        let mut model_voxels = vec![BLANK; (width * height * depth) as usize];
+        for (position, color) in voxels {
+            let v = (*position - min).as_uvec3();
+            model_voxels[position_to_index(width, depth, v.x, v.z, v.y)] = *color;
         }
+
+        let model = Self {
+            width,
+            height,
+            depth,
+            voxels: model_voxels,
+        };
+        (min, model)
+    }
+
+    pub fn dimensions(&self) -> UVec3 {
+        UVec3::new(self.width, self.height, self.depth)
     }
 
-    fn as_ref(&self) -> ModelDataRef {
+    fn as_ref(&self) -> ModelDataRef<'_> {
         ModelDataRef {
-            dimension: self.dimension,
+            width: self.width,
+            height: self.height,
+            depth: self.depth,
             voxels: &self.voxels,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct ModelDataRef<'a> {
-    pub dimension: u32,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
     pub voxels: &'a [Color],
 }
 
 impl<'a> ModelDataRef<'a> {
     #[inline]
     pub fn get_voxel(&self, x: u32, y: u32, z: u32) -> Option<Color> {
-        let index = position_to_index(self.dimension, x, y, z);
+        if x >= self.width || y >= self.height || z >= self.depth {
+            return None;
+        }
+        let index = position_to_index(self.width, self.depth, x, y, z);
         self.voxels.get(index).copied()
     }
 }
 
+/// Models of a vox file, each placed at its position in the scene.
+#[derive(Debug)]
+pub struct VoxScene {
+    /// Size of the box around all models, with the y-axis pointing upwards.
+    pub size: UVec3,
+    /// Models with the corner of each one in the box, in voxels.
+    pub models: Vec<(UVec3, ModelData)>,
+}
+
+impl VoxScene {
+    /// Places all models of the scene at their positions
+    /// and fits the box around them.
+    pub fn from_vox_data(data: &DotVoxData) -> Self {
+        // Voxel positions of each model in the vox coordinates
+        let models: Vec<(IVec3, ModelData)> = flatten_vox_scene(data)
+            .into_iter()
+            .map(|placed| {
+                let model = &data.models[placed.model_index];
+                let size = IVec3::new(
+                    model.size.x as i32,
+                    model.size.y as i32,
+                    model.size.z as i32,
+                );
+                // Models are rotated around their center
+                let pivot = (size / 2).as_vec3();
+                let voxels: Vec<(IVec3, Color)> = model
+                    .voxels
+                    .iter()
+                    .map(|v| {
+                        let center =
+                            Vec3::new(v.x as f32, v.y as f32, v.z as f32) + 0.5 - pivot;
+                        let position = placed.rotation * center + placed.translation;
+                        (position.floor().as_ivec3(), data.palette[v.i as usize])
+                    })
+                    .collect();
+                ModelData::from_voxels(&voxels)
+            })
+            .filter(|(_, model)| !model.voxels.is_empty())
+            .collect();
+        if models.is_empty() {
+            return Self {
+                size: UVec3::ZERO,
+                models: Vec::new(),
+            };
+        }
+
+        // Vox corners of the models, where the size is in the y-up coordinates
+        let vox_size = |model: &ModelData| {
+            IVec3::new(model.width as i32, model.depth as i32, model.height as i32)
+        };
+        let min = models
+            .iter()
+            .fold(IVec3::MAX, |min, (corner, _)| min.min(*corner));
+        let max = models.iter().fold(IVec3::MIN, |max, (corner, model)| {
+            max.max(*corner + vox_size(model))
+        });
+        let size = (max - min).as_uvec3();
+        Self {
+            size: UVec3::new(size.x, size.z, size.y),
+            models: models
+                .into_iter()
+                .map(|(corner, model)| {
+                    let offset = (corner - min).as_uvec3();
+                    (UVec3::new(offset.x, offset.z, offset.y), model)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A model from the vox file with its transform in the scene,
+/// using the vox coordinates where the z axis points up.
+#[derive(Debug, Clone, Copy)]
+pub struct PlacedVoxModel {
+    /// Index of the model in the vox file.
+    pub model_index: usize,
+    pub rotation: Mat3,
+    /// Position of the model center.
+    pub translation: Vec3,
+}
+
+/// Walks the scene graph of the vox file and returns each model in it with
+/// the transforms of all its parents combined. Models in hidden layers are
+/// skipped. Files without a scene graph place all models at the origin.
+pub fn flatten_vox_scene(data: &DotVoxData) -> Vec<PlacedVoxModel> {
+    if data.scenes.is_empty() {
+        return (0..data.models.len())
+            .map(|model_index| PlacedVoxModel {
+                model_index,
+                rotation: Mat3::IDENTITY,
+                translation: Vec3::ZERO,
+            })
+            .collect();
+    }
+    let mut placed = Vec::new();
+    flatten_scene_node(data, 0, Mat3::IDENTITY, Vec3::ZERO, &mut placed);
+    placed
+}
+
+fn flatten_scene_node(
+    data: &DotVoxData,
+    node: usize,
+    rotation: Mat3,
+    translation: Vec3,
+    placed: &mut Vec<PlacedVoxModel>,
+) {
+    match &data.scenes[node] {
+        SceneNode::Transform {
+            frames,
+            child,
+            layer_id,
+            ..
+        } => {
+            let is_hidden = data
+                .layers
+                .get(*layer_id as usize)
+                .is_some_and(|layer| layer.hidden());
+            if is_hidden {
+                return;
+            }
+            // Only the first frame is used since animations are not supported
+            let (local_rotation, local_translation) = frames
+                .first()
+                .map(|frame| {
+                    let rotation = frame
+                        .orientation()
+                        .map(|r| Mat3::from_cols_array_2d(&r.to_cols_array_2d()))
+                        .unwrap_or(Mat3::IDENTITY);
+                    let translation = frame
+                        .position()
+                        .map(|p| Vec3::new(p.x as f32, p.y as f32, p.z as f32))
+                        .unwrap_or(Vec3::ZERO);
+                    (rotation, translation)
+                })
+                .unwrap_or((Mat3::IDENTITY, Vec3::ZERO));
+            flatten_scene_node(
+                data,
+                *child as usize,
+                rotation * local_rotation,
+                translation + rotation * local_translation,
+                placed,
+            );
+        }
+        SceneNode::Group { children, .. } => {
+            for child in children {
+                flatten_scene_node(data, *child as usize, rotation, translation, placed);
+            }
+        }
+        SceneNode::Shape { models, .. } => {
+            placed.extend(models.iter().map(|model| PlacedVoxModel {
+                model_index: model.model_id as usize,
+                rotation,
+                translation,
+            }));
+        }
+    }
+}
+
 // TODO Change input to usize or something
-fn position_to_index(dimension: u32, x: u32, y: u32, z: u32) -> usize {
-    (x + z * dimension + y * dimension * dimension) as usize
+fn position_to_index(width: u32, depth: u32, x: u32, y: u32, z: u32) -> usize {
+    (x + z * width + y * width * depth) as usize
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ModelID(pub usize);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scene_models_keep_their_positions() {
+        use dot_vox::{Frame, Model, ShapeModel, Size, Voxel};
+
+        let single_voxel = || Model {
+            size: Size { x: 1, y: 1, z: 1 },
+            voxels: vec![Voxel {
+                x: 0,
+                y: 0,
+                z: 0,
+                i: 0,
+            }],
+        };
+        let transform = |position: &str, child| SceneNode::Transform {
+            attributes: Default::default(),
+            frames: vec![Frame::new([("_t".to_owned(), position.to_owned())].into())],
+            child,
+            layer_id: 0,
+        };
+        let shape = |model_id| SceneNode::Shape {
+            attributes: Default::default(),
+            models: vec![ShapeModel {
+                model_id,
+                attributes: Default::default(),
+            }],
+        };
+        let data = DotVoxData {
+            version: 150,
+            models: vec![single_voxel(), single_voxel()],
+            palette: vec![Color {
+                r: 255,
+                g: 0,
+                b: 0,
+                a: 255,
+            }],
+            materials: Vec::new(),
+            scenes: vec![
+                transform("0 0 0", 1),
+                SceneNode::Group {
+                    attributes: Default::default(),
+                    children: vec![2, 4],
+                },
+                transform("0 0 0", 3),
+                shape(0),
+                // Vox coordinates have the z axis pointing up
+                transform("3 0 2", 5),
+                shape(1),
+            ],
+            layers: Vec::new(),
+        };
+
+        let scene = VoxScene::from_vox_data(&data);
+        assert_eq!(scene.size, UVec3::new(4, 3, 1));
+        let offsets: Vec<UVec3> =
+            scene.models.iter().map(|(offset, _)| *offset).collect();
+        assert_eq!(offsets, [UVec3::ZERO, UVec3::new(3, 2, 0)]);
+    }
+}
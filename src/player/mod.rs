@@ -1,13 +1,12 @@
 mod physics;
 
-use std::f32::consts::PI;
-
-use glam::{Vec2, Vec3};
+use glam::Vec3;
 use winit::event::MouseScrollDelta;
 
 use crate::{
     control::GameInput,
-    map::{portal::Rotation, room::RoomID, Map},
+    map::{portal::PortalTransform, room::RoomID, tilemap::Spawn, Map},
+    raycaster::camera::CameraTarget,
 };
 
 use self::physics::{CylinderBody, PhysicsStateDebugData};
@@ -17,18 +16,20 @@ pub struct Player {
 
     current_room: RoomID,
     use_flashlight: bool,
+    score: u32,
 }
 
 impl Player {
     // TODO positive pitch should make camera look UP, not DOWN!!!?
-    pub fn new(current_room: RoomID) -> Self {
+    pub fn new(current_room: RoomID, spawn: Spawn) -> Self {
         let body = CylinderBody::new(
-            Vec3::new(5.5, 2.0, 4.5),
-            90.0f32.to_radians(),
+            spawn.position,
+            spawn.yaw,
             0.0,
             0.2,
             2.0,
             0.9,
+            0.4,
             1.2,
             3.5,
             3.0,
@@ -41,18 +42,24 @@ impl Player {
             body,
             current_room,
             use_flashlight: false,
+            score: 0,
         }
     }
 
     pub fn update(&mut self, map: &Map, delta: f32) {
-        let mut room = map.get_room_data(self.current_room);
+        let room = map.get_room_data(self.current_room);
 
-        self.body.update_physics(delta);
+        let current_room = self.current_room;
+        let feet_height = self.body.feet_position.y;
+        self.body.update_physics(delta, |x, z| {
+            map.get_tile_through_portals(current_room, x, z, feet_height)
+        });
 
         // Teleportation between rooms
-        if let Some(tile) = room.tilemap.get_tile_checked(
+        if let Some(tile) = room.get_span_checked(
             self.body.feet_position.x as i64,
             self.body.feet_position.z as i64,
+            self.body.feet_position.y,
         ) {
             // Check if tile has a portal
             if let Some(id) = tile.portal_id {
@@ -63,51 +70,19 @@ impl Player {
                     self.current_room = room_id;
                     let dest_room = map.get_room_data(room_id);
                     let dest_portal = dest_room.get_portal(dest_id);
-                    room = dest_room;
-
-                    let player_map_pos = Vec2::new(self.body.feet_position.x, self.body.feet_position.z);
-                    let offset = player_map_pos - src_portal.center;
-
-                    let src_angle = f32::atan2(src_portal.direction.y, src_portal.direction.x);
-                    let dest_angle = f32::atan2(-dest_portal.direction.y, -dest_portal.direction.x);
-                    let diff = dest_angle - src_angle;
-                    let rotation = glam::mat2(Vec2::new(diff.cos(), diff.sin()), Vec2::new(-diff.sin(), diff.cos()));
-                    let rotated_offset = rotation * offset;
-                    println!("src: {}, dest: {}", src_portal.direction, dest_portal.direction);
-                    let new_position = dest_portal.center + rotated_offset + (-dest_portal.direction);
-                    self.body.feet_position = Vec3::new(new_position.x, self.body.feet_position.y + dest_portal.ground_height - src_portal.ground_height, new_position.y);
-
-                    self.body.add_yaw(diff);
-
-                    //let dest_room = map.get_room_data(room_id);
-                    //let dest_portal = dest_room.get_portal(portal_id);
-
-                    //let new_origin =
-                    //    src_portal.teleport_to(self.body.feet_position, dest_portal);
-                    //let yaw_angle_difference =
-                    //    match src_portal.direction_difference(&dest_portal) {
-                    //        Rotation::Deg0 => PI,
-                    //        Rotation::AnticlockwiseDeg90 => PI * 0.5,
-                    //        Rotation::ClockwiseDeg90 => -PI * 0.5,
-                    //        Rotation::Deg180 => 0.0,
-                    //    };
-                    //self.body.feet_position = new_origin;
-                    //self.body.add_yaw(yaw_angle_difference);
+                    self.body
+                        .pass_portal(&PortalTransform::new(&src_portal, &dest_portal));
                 }
             }
         }
-        self.body.collision_detection_resolution(room.tilemap);
     }
-    
+
     pub fn current_room_id(&self) -> RoomID {
         self.current_room
     }
 
-    pub fn current_tile_pos(&self) -> (i64, i64) {
-        (
-            self.body.feet_position.x as i64,
-            self.body.feet_position.z as i64,
-        )
+    pub fn feet_position(&self) -> Vec3 {
+        self.body.feet_position
     }
 
     pub fn use_flashlight(&self) -> bool {
@@ -131,17 +106,17 @@ impl Player {
         &self.body
     }
 
-    /*pub fn collect_dbg_data(&self) -> PlayerDebugData {
+    pub fn collect_dbg_data(&self) -> PlayerDebugData {
+        let target = self.body.get_target_data();
         PlayerDebugData {
             score: self.score,
-            eye_pos: self.camera.origin,
-            forward_dir: self.camera.forward_dir,
-            yaw_angle: self.camera.yaw_angle.to_degrees(),
-            y_shearing: self.camera.y_shearing,
+            eye_pos: target.origin,
+            forward_dir: target.forward_dir,
+            yaw_angle: target.yaw.to_degrees(),
             current_room_id: self.current_room.0,
             physics_state: self.body.collect_dbg_data(),
         }
-    }*/
+    }
 }
 
 #[derive(Debug)]
@@ -150,7 +125,6 @@ pub struct PlayerDebugData {
     pub eye_pos: Vec3,
     pub forward_dir: Vec3,
     pub yaw_angle: f32,
-    pub y_shearing: f32,
     pub current_room_id: usize,
     pub physics_state: PhysicsStateDebugData,
 }
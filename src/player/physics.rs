@@ -5,13 +5,20 @@ use glam::{Vec2, Vec3};
 
 use crate::{
     control::GameInput,
-    map::tilemap::Tilemap,
+    map::{portal::PortalTransform, tilemap::TilePlatforms},
     raycaster::camera::{normalize_rad, CameraTarget, CameraTargetData},
 };
 
 const MOVEMENT_CONST: f32 = 1.5;
 const VERTICAL_MOVEMENT_CONST: f32 = 10.0;
-const TILE_COLLISION_OFFSET: f32 = 0.4;
+/// How far into a lower ceiling the head can reach before the tile blocks the body.
+const CEILING_COLLISION_OFFSET: f32 = 0.4;
+/// How fast the view catches up with the body after a step, higher is faster.
+const STEP_EASING_SPEED: f32 = 12.0;
+/// Distance kept between the body and the tiles it hits.
+const COLLISION_SKIN: f32 = 0.001;
+/// Most times the body can hit a tile and slide along it in one update.
+const MAX_SLIDES: u32 = 4;
 const ACCELERATION_CONST: f32 = 10.0;
 const SLOWDOWN_CONST: f32 = 10.0;
 
@@ -25,6 +32,11 @@ pub struct CylinderBody {
     radius: f32,
     height: f32,
     eye_height: f32,
+    /// Highest ledge the body can walk onto and lowest step it can walk down.
+    max_step_height: f32,
+    /// Difference between the eye height and the height where the eyes are shown,
+    /// which slowly goes away so the view doesn't jerk on steps.
+    step_offset: f32,
 
     is_ghost: bool,
     can_fly: bool,
@@ -52,6 +64,7 @@ impl CylinderBody {
         radius: f32,
         height: f32,
         eye_height_factor: f32,
+        max_step_height: f32,
 
         jump_strength: f32,
         movement_accel: f32,
@@ -76,6 +89,8 @@ impl CylinderBody {
             radius,
             height,
             eye_height: eye_height_factor * height,
+            max_step_height,
+            step_offset: 0.0,
 
             is_ghost: false,
             can_fly: false,
@@ -93,127 +108,204 @@ impl CylinderBody {
         }
     }
 
-    pub fn collision_detection_resolution(&mut self, blueprint: &Tilemap) {
-        if self.is_ghost {
+    /// Moves the body by the `displacement`, but stops it at the first tile
+    /// it can't fit in and slides it along that tile for the rest of the way.
+    /// Tiles are found with `get_tile`, which returns `None` outside of the map.
+    fn move_and_collide<F>(&mut self, displacement: Vec3, get_tile: F)
+    where
+        F: Fn(i64, i64) -> Option<TilePlatforms>,
+    {
+        let start = self.feet_position;
+        if get_tile(start.x.floor() as i64, start.z.floor() as i64).is_none() {
+            self.feet_position += displacement;
             return;
         }
-
-        let Some(current_tile) = blueprint
-            .get_tile_checked(self.feet_position.x as i64, self.feet_position.z as i64)
-        else {
-            return;
+        // Tiles which can't be entered at the starting height,
+        // sloped tiles are entered at the point closest to the body
+        let is_blocking = |x: i64, z: i64| {
+            get_tile(x, z).is_none_or(|tile| {
+                let min = Vec2::new(x as f32, z as f32);
+                let closest = Vec2::new(start.x, start.z).clamp(min, min + Vec2::ONE);
+                (tile.ground_height_at(closest.x, closest.y) - self.max_step_height)
+                    > start.y
+                    || (tile.ceiling_height_at(closest.x, closest.y)
+                        + CEILING_COLLISION_OFFSET)
+                        < (start.y + self.height)
+            })
         };
-        let mut ground_level = current_tile.ground_height;
-        let mut ceiling_level = current_tile.ceiling_height;
-
-        let pos_x = current_tile.position.x as i64;
-        let pos_z = current_tile.position.z as i64;
-        let intersected_vertical =
-            if (self.feet_position.x + self.radius) > (pos_x as f32 + 1.0) {
-                if let Some(tile) = blueprint.get_tile_checked(pos_x + 1, pos_z) {
-                    if (tile.ground_height - TILE_COLLISION_OFFSET) > self.feet_position.y
-                        || (tile.ceiling_height + TILE_COLLISION_OFFSET)
-                            < (self.feet_position.y + self.height)
-                    {
-                        self.feet_position.x = (pos_x as f32 + 1.0) - self.radius;
-                        //self.movement_velocity.x = 0.0;
-                    } else {
-                        ground_level = ground_level.max(tile.ground_height);
-                        ceiling_level = ceiling_level.min(tile.ceiling_height);
-                    }
-                }
-                Some(IntersectedVerticalSide::Right)
-            } else if (self.feet_position.x - self.radius) < pos_x as f32 {
-                if let Some(tile) = blueprint.get_tile_checked(pos_x - 1, pos_z) {
-                    if (tile.ground_height - TILE_COLLISION_OFFSET) > self.feet_position.y
-                        || (tile.ceiling_height + TILE_COLLISION_OFFSET)
-                            < (self.feet_position.y + self.height)
-                    {
-                        self.feet_position.x = pos_x as f32 + self.radius;
-                        //self.movement_velocity.x = 0.0;
-                    } else {
-                        ground_level = ground_level.max(tile.ground_height);
-                        ceiling_level = ceiling_level.min(tile.ceiling_height);
-                    }
-                }
-                Some(IntersectedVerticalSide::Left)
-            } else {
-                None
-            };
-        let intersected_horizontal =
-            if (self.feet_position.z + self.radius) > (pos_z as f32 + 1.0) {
-                if let Some(tile) = blueprint.get_tile_checked(pos_x, pos_z + 1) {
-                    if (tile.ground_height - TILE_COLLISION_OFFSET) > self.feet_position.y
-                        || (tile.ceiling_height + TILE_COLLISION_OFFSET)
-                            < (self.feet_position.y + self.height)
-                    {
-                        self.feet_position.z = (pos_z as f32 + 1.0) - self.radius;
-                        //self.movement_velocity.y = 0.0;
-                    } else {
-                        ground_level = ground_level.max(tile.ground_height);
-                        ceiling_level = ceiling_level.min(tile.ceiling_height);
-                    }
-                }
-                Some(IntersectedHorizontalSide::Top)
-            } else if (self.feet_position.z - self.radius) < pos_z as f32 {
-                if let Some(tile) = blueprint.get_tile_checked(pos_x, pos_z - 1) {
-                    if (tile.ground_height - TILE_COLLISION_OFFSET) > self.feet_position.y
-                        || (tile.ceiling_height + TILE_COLLISION_OFFSET)
-                            < (self.feet_position.y + self.height)
-                    {
-                        self.feet_position.z = pos_z as f32 + self.radius;
-                        //self.movement_velocity.y = 0.0;
-                    } else {
-                        ground_level = ground_level.max(tile.ground_height);
-                        ceiling_level = ceiling_level.min(tile.ceiling_height);
-                    }
-                }
-                Some(IntersectedHorizontalSide::Bottom)
-            } else {
-                None
+
+        let mut position =
+            self.push_out_of_tiles(Vec2::new(start.x, start.z), &is_blocking);
+        let mut remaining = Vec2::new(displacement.x, displacement.z);
+        let mut slides = 0;
+        while remaining.length() > COLLISION_SKIN && slides < MAX_SLIDES {
+            // Sweep at most one tile at once, so only the closest tiles are checked
+            let step = remaining.clamp_length_max(1.0);
+            let Some((time, normal)) = self.sweep(position, step, &is_blocking) else {
+                position += step;
+                remaining -= step;
+                continue;
             };
-        if let (Some(v), Some(h)) = (intersected_vertical, intersected_horizontal) {
-            let offset = (v as i64, h as i64);
-            let (offset_x, offset_z) = (offset.0 * 2 - 1, offset.1 * 2 - 1);
-            if let Some(tile) =
-                blueprint.get_tile_checked(pos_x + offset_x, pos_z + offset_z)
-            {
-                if (tile.ground_height - TILE_COLLISION_OFFSET) > self.feet_position.y
-                    || (tile.ceiling_height + TILE_COLLISION_OFFSET)
-                        < (self.feet_position.y + self.height)
-                {
-                    let edge_x = (pos_x + offset.0) as f32;
-                    let edge_z = (pos_z + offset.1) as f32;
-                    let dist_x = edge_x - self.feet_position.x;
-                    let dist_z = edge_z - self.feet_position.z;
-                    if dist_x.abs() > dist_z.abs() {
-                        self.feet_position.x = edge_x - offset_x as f32 * self.radius;
-                        //self.movement_velocity.x = 0.0;
-                    } else {
-                        self.feet_position.z = edge_z - offset_z as f32 * self.radius;
-                        //self.movement_velocity.y = 0.0;
-                    }
-                } else {
-                    ground_level = ground_level.max(tile.ground_height);
-                    ceiling_level = ceiling_level.min(tile.ceiling_height);
-                }
-            }
+            position += step * time + normal * COLLISION_SKIN;
+            remaining -= step * time;
+            // Slide along the wall with the rest of the movement
+            remaining -= normal * remaining.dot(normal);
+            self.movement_velocity -=
+                normal * self.movement_velocity.dot(normal).min(0.0);
+            slides += 1;
+        }
+
+        let (ground_level, ceiling_level) =
+            self.platform_levels(position, position, &get_tile, &is_blocking);
+        self.feet_position = Vec3::new(position.x, start.y + displacement.y, position.y);
+
+        // Stay on the ground when walking down steps instead of falling off them
+        let was_grounded = self.is_grounded;
+        let height_above_ground = self.feet_position.y - ground_level;
+        if was_grounded
+            && self.air_velocity <= 0.0
+            && (0.0..=self.max_step_height).contains(&height_above_ground)
+        {
+            self.feet_position.y = ground_level;
         }
 
         if self.feet_position.y < ground_level {
             self.feet_position.y = ground_level;
             self.air_velocity = 0.0;
         } else if (self.feet_position.y + self.height) > ceiling_level {
-            self.feet_position.y = ceiling_level - self.height;
+            // Closing ceilings never push the body through the ground, it gets
+            // pushed out of the tile once the ceiling is too low instead
+            self.feet_position.y = (ceiling_level - self.height).max(ground_level);
             self.air_velocity = 0.0;
         }
         self.is_grounded = self.feet_position.y <= ground_level;
         if self.is_grounded {
             self.air_velocity = 0.0;
         }
+        // Keep the eyes where they were when stepping up or down and ease them later.
+        // Following the ground the body stood on, like slopes and moving platforms,
+        // moves the eyes right away.
+        if was_grounded && self.is_grounded {
+            let start_position = Vec2::new(start.x, start.z);
+            let followed_ground = self
+                .platform_levels(start_position, position, &get_tile, &is_blocking)
+                .0;
+            if followed_ground.is_finite() {
+                self.step_offset += followed_ground - self.feet_position.y;
+            }
+        }
+    }
+
+    /// Returns the highest ground and the lowest ceiling of all the tiles the body
+    /// is in at the `position`, with sloped platforms sampled at the `feet`.
+    /// Tiles which block the body are never entered.
+    fn platform_levels<F, B>(
+        &self,
+        position: Vec2,
+        feet: Vec2,
+        get_tile: &F,
+        is_blocking: &B,
+    ) -> (f32, f32)
+    where
+        F: Fn(i64, i64) -> Option<TilePlatforms>,
+        B: Fn(i64, i64) -> bool,
+    {
+        let mut ground_level = f32::NEG_INFINITY;
+        let mut ceiling_level = f32::INFINITY;
+        for (x, z) in self.covered_tiles(position, position) {
+            if !self.overlaps_tile(position, x, z) || is_blocking(x, z) {
+                continue;
+            }
+            if let Some(tile) = get_tile(x, z) {
+                ground_level = ground_level.max(tile.ground_height_at(feet.x, feet.y));
+                ceiling_level = ceiling_level.min(tile.ceiling_height_at(feet.x, feet.y));
+            }
+        }
+        (ground_level, ceiling_level)
+    }
+
+    /// Returns the time in range `[0, 1]` of the first hit with a blocking tile
+    /// while moving from the `position` by the `step`, with the normal of the hit.
+    fn sweep<F>(&self, position: Vec2, step: Vec2, is_blocking: &F) -> Option<(f32, Vec2)>
+    where
+        F: Fn(i64, i64) -> bool,
+    {
+        let r = self.radius;
+        self.covered_tiles(position, position + step)
+            .filter(|&(x, z)| is_blocking(x, z))
+            .filter_map(|(x, z)| {
+                // Tile grown by the radius, so the body can be swept as a point
+                let min = Vec2::new(x as f32, z as f32);
+                let max = min + Vec2::ONE;
+                let (grow_x, grow_z) = (Vec2::new(r, 0.0), Vec2::new(0.0, r));
+                let corners =
+                    [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)];
+                [
+                    sweep_box(position, step, min - grow_x, max + grow_x),
+                    sweep_box(position, step, min - grow_z, max + grow_z),
+                ]
+                .into_iter()
+                .chain(corners.map(|corner| sweep_circle(position, step, corner, r)))
+                .flatten()
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+
+    /// Pushes the body out of the blocking tiles it is already in,
+    /// like after passing through a portal right next to a wall.
+    fn push_out_of_tiles<F>(&self, mut position: Vec2, is_blocking: &F) -> Vec2
+    where
+        F: Fn(i64, i64) -> bool,
+    {
+        for (x, z) in self.covered_tiles(position, position) {
+            if !is_blocking(x, z) || !self.overlaps_tile(position, x, z) {
+                continue;
+            }
+            let min = Vec2::new(x as f32, z as f32);
+            let closest = position.clamp(min, min + Vec2::ONE);
+            let offset = position - closest;
+            if offset != Vec2::ZERO {
+                position = closest + offset.normalize() * (self.radius + COLLISION_SKIN);
+            } else {
+                // The center is inside the tile, so leave through the closest side
+                let (to_min, to_max) = (position - min, min + Vec2::ONE - position);
+                let sides = [
+                    (to_min.x, Vec2::NEG_X),
+                    (to_max.x, Vec2::X),
+                    (to_min.y, Vec2::NEG_Y),
+                    (to_max.y, Vec2::Y),
+                ];
+                let (distance, normal) = sides
+                    .into_iter()
+                    .min_by(|a, b| a.0.total_cmp(&b.0))
+                    .unwrap();
+                position += normal * (distance + self.radius + COLLISION_SKIN);
+            }
+        }
+        position
     }
 
-    pub fn update_physics(&mut self, delta: f32) {
+    /// Returns the coordinates of all tiles which the body can touch
+    /// while moving in a straight line between two positions.
+    fn covered_tiles(&self, from: Vec2, to: Vec2) -> impl Iterator<Item = (i64, i64)> {
+        let min = (from.min(to) - Vec2::splat(self.radius)).floor();
+        let max = (from.max(to) + Vec2::splat(self.radius)).floor();
+        (min.y as i64..=max.y as i64)
+            .flat_map(move |z| (min.x as i64..=max.x as i64).map(move |x| (x, z)))
+    }
+
+    fn overlaps_tile(&self, position: Vec2, x: i64, z: i64) -> bool {
+        let min = Vec2::new(x as f32, z as f32);
+        position.distance(position.clamp(min, min + Vec2::ONE)) < self.radius
+    }
+
+    /// Moves the body for the passed time. Tiles are found with `get_tile`,
+    /// which returns `None` outside of the map.
+    pub fn update_physics<F>(&mut self, delta: f32, get_tile: F)
+    where
+        F: Fn(i64, i64) -> Option<TilePlatforms>,
+    {
         let movement = self.input_state.movement();
         let (horizontal_movement, vertical_movement) = (movement.x, movement.y);
         let movement_dir =
@@ -244,9 +336,17 @@ impl CylinderBody {
             self.air_velocity = self.jump_strength;
         }
 
-        self.feet_position.x += self.movement_velocity.x * delta * MOVEMENT_CONST;
-        self.feet_position.z += self.movement_velocity.y * delta * MOVEMENT_CONST;
-        self.feet_position.y += self.air_velocity * delta * VERTICAL_MOVEMENT_CONST;
+        self.step_offset *= (-STEP_EASING_SPEED * delta).exp();
+        let displacement = Vec3::new(
+            self.movement_velocity.x * delta * MOVEMENT_CONST,
+            self.air_velocity * delta * VERTICAL_MOVEMENT_CONST,
+            self.movement_velocity.y * delta * MOVEMENT_CONST,
+        );
+        if self.is_ghost {
+            self.feet_position += displacement;
+        } else {
+            self.move_and_collide(displacement, get_tile);
+        }
 
         // Maybe will be used when floor gets a friction attribute
         self.movement_velocity /= 1.0 + self.friction * delta;
@@ -258,6 +358,19 @@ impl CylinderBody {
         }
     }
 
+    /// Moves the body through the portal, keeping its velocity
+    /// and view direction relative to the portal.
+    pub fn pass_portal(&mut self, transform: &PortalTransform) {
+        self.feet_position = transform.position(self.feet_position);
+        let velocity = transform.direction(Vec3::new(
+            self.movement_velocity.x,
+            0.0,
+            self.movement_velocity.y,
+        ));
+        self.movement_velocity = Vec2::new(velocity.x, velocity.z);
+        self.add_yaw(transform.yaw());
+    }
+
     /// Angle increases in a counter clockwise direction.
     pub fn add_yaw(&mut self, add: f32) {
         self.set_yaw(self.yaw + add);
@@ -270,6 +383,7 @@ impl CylinderBody {
     pub fn set_yaw(&mut self, yaw: f32) {
         self.yaw = normalize_rad(yaw);
         self.forward_dir = Vec3::new(self.yaw.cos(), 0.0, self.yaw.sin());
+        self.right_dir = Vec3::new(self.forward_dir.z, 0.0, -self.forward_dir.x);
     }
 
     pub fn set_pitch(&mut self, pitch: f32) {
@@ -302,8 +416,6 @@ impl CylinderBody {
 
     pub fn collect_dbg_data(&self) -> PhysicsStateDebugData {
         PhysicsStateDebugData {
-            radius: self.radius,
-            height: self.height,
             is_ghost: self.is_ghost,
             can_fly: self.can_fly,
             movement_velocity: self.movement_velocity,
@@ -313,14 +425,56 @@ impl CylinderBody {
     }
 }
 
-enum IntersectedVerticalSide {
-    Left,
-    Right,
+/// Returns the time in range `[0, 1]` at which the point moving from the
+/// `position` by the `step` enters the box, with the normal of the entered side.
+fn sweep_box(position: Vec2, step: Vec2, min: Vec2, max: Vec2) -> Option<(f32, Vec2)> {
+    let mut enter = (f32::NEG_INFINITY, Vec2::ZERO);
+    let mut exit = f32::INFINITY;
+    for (axis, normal) in [(0, Vec2::X), (1, Vec2::Y)] {
+        if step[axis] == 0.0 {
+            if position[axis] <= min[axis] || position[axis] >= max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let t_min = (min[axis] - position[axis]) / step[axis];
+        let t_max = (max[axis] - position[axis]) / step[axis];
+        let (near, far, normal) = if t_min < t_max {
+            (t_min, t_max, -normal)
+        } else {
+            (t_max, t_min, normal)
+        };
+        if near > enter.0 {
+            enter = (near, normal);
+        }
+        exit = exit.min(far);
+    }
+    // Points which start inside the box are pushed out before sweeping
+    (enter.0 >= 0.0 && enter.0 <= 1.0 && enter.0 < exit).then_some(enter)
 }
 
-enum IntersectedHorizontalSide {
-    Bottom,
-    Top,
+/// Returns the time in range `[0, 1]` at which the point moving from the
+/// `position` by the `step` enters the circle, with the normal at that point.
+fn sweep_circle(
+    position: Vec2,
+    step: Vec2,
+    center: Vec2,
+    radius: f32,
+) -> Option<(f32, Vec2)> {
+    let offset = position - center;
+    let a = step.length_squared();
+    let b = offset.dot(step);
+    let c = offset.length_squared() - radius * radius;
+    // Only entering the circle counts as a hit
+    if a == 0.0 || c < 0.0 || b >= 0.0 {
+        return None;
+    }
+    let discriminant = b * b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let time = (-b - discriminant.sqrt()) / a;
+    (time <= 1.0).then(|| (time, (offset + step * time) / radius))
 }
 
 #[derive(Debug, Default)]
@@ -352,7 +506,7 @@ impl CameraTarget for CylinderBody {
         CameraTargetData {
             origin: Vec3::new(
                 self.feet_position.x,
-                self.feet_position.y + self.eye_height,
+                self.feet_position.y + self.eye_height + self.step_offset,
                 self.feet_position.z,
             ),
             forward_dir: self.forward_dir,
@@ -365,11 +519,400 @@ impl CameraTarget for CylinderBody {
 
 #[derive(Debug)]
 pub struct PhysicsStateDebugData {
-    pub radius: f32,
-    pub height: f32,
     pub is_ghost: bool,
     pub can_fly: bool,
     pub movement_velocity: Vec2,
     pub air_velocity: f32,
     pub is_grounded: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        map::{
+            parser::parse_test_blueprint,
+            portal::{Portal, PortalID},
+            room::{Room, RoomID, RoomRef},
+            tilemap::{Slope, TextureMapping, Tile},
+        },
+        raycaster::PointXZ,
+        textures::TextureID,
+    };
+
+    fn test_body(position: Vec3) -> CylinderBody {
+        CylinderBody::new(
+            position, 0.0, 0.0, 0.2, 2.0, 0.9, 0.4, 1.2, 3.5, 1.0e6, -4.0, 2.5, 0.0,
+        )
+    }
+
+    /// Room of 20x8 tiles with the ground at 0 and the ceiling at 4,
+    /// where `shape` changes the tiles in each row along the `x` axis.
+    fn test_room<S>(shape: S) -> impl Fn(i64, i64) -> Option<TilePlatforms>
+    where
+        S: Fn(i64, &mut Tile),
+    {
+        move |x, z| {
+            if !(0..20).contains(&x) || !(0..8).contains(&z) {
+                return None;
+            }
+            let mut tile = Tile {
+                position: PointXZ::new(x as u64, z as u64),
+                bottom_wall_texs: [TextureID(0); 4],
+                top_wall_texs: [TextureID(0); 4],
+                ground_tex: TextureID(0),
+                ceiling_tex: TextureID(0),
+                bottom_wall_mapping: TextureMapping::default(),
+                top_wall_mapping: TextureMapping::default(),
+                ground_mapping: TextureMapping::default(),
+                ceiling_mapping: TextureMapping::default(),
+                bottom_height: -1.0,
+                ground_height: 0.0,
+                ceiling_height: 4.0,
+                top_height: 5.0,
+                portal_id: None,
+                ground_slope: Slope::default(),
+                ceiling_slope: Slope::default(),
+                mover_trigger: None,
+                objects: Vec::new(),
+            };
+            shape(x, &mut tile);
+            Some(tile.platforms())
+        }
+    }
+
+    /// Room with a single tile thick wall at `x` = 10.
+    fn walled_room() -> impl Fn(i64, i64) -> Option<TilePlatforms> {
+        test_room(|x, tile| {
+            if x == 10 {
+                tile.ground_height = 4.0;
+            }
+        })
+    }
+
+    #[test]
+    fn no_tunnelling_at_extreme_velocity() {
+        for velocity in [1.0e3, 1.0e4, 1.0e6] {
+            let mut body = test_body(Vec3::new(2.5, 0.0, 4.5));
+            body.movement_velocity = Vec2::new(velocity, 0.0);
+            body.update_physics(1.0, walled_room());
+            let x = body.feet_position.x;
+            assert!(
+                x <= 10.0 - body.radius && x > 9.0,
+                "went through the wall to x = {} at velocity {}",
+                x,
+                velocity
+            );
+        }
+    }
+
+    #[test]
+    fn no_tunnelling_at_low_frame_rate() {
+        let room = walled_room();
+        let mut body = test_body(Vec3::new(9.5, 0.0, 4.5));
+        for delta in [5.0, 0.1, 5.0] {
+            body.movement_velocity = Vec2::new(3.0, 0.5);
+            body.update_physics(delta, &room);
+            assert!(body.feet_position.x < 10.0, "x = {}", body.feet_position.x);
+        }
+    }
+
+    #[test]
+    fn no_tunnelling_through_wall_corners() {
+        // Aim at the corners where the wall meets the border of the room
+        for direction in [
+            Vec2::new(1.0, 1.0),
+            Vec2::new(1.0, -1.0),
+            Vec2::new(3.0, 0.1),
+        ] {
+            let mut body = test_body(Vec3::new(5.5, 0.0, 4.5));
+            body.movement_velocity = direction.normalize() * 1.0e5;
+            body.update_physics(1.0, walled_room());
+            let position = body.feet_position;
+            assert!(
+                position.x <= 10.0 - body.radius
+                    && (body.radius..=8.0 - body.radius).contains(&position.z),
+                "left the room to {} moving in direction {}",
+                position,
+                direction
+            );
+        }
+    }
+
+    #[test]
+    fn slides_along_walls() {
+        let mut body = test_body(Vec3::new(8.5, 0.0, 1.5));
+        // Slowed down to 40 / 26 by the slowdown friction, so moves 2.3 on each axis
+        body.movement_velocity = Vec2::new(40.0, 40.0);
+        body.update_physics(1.0, walled_room());
+        let position = body.feet_position;
+        assert!(
+            (position.x - (10.0 - body.radius)).abs() < 0.01,
+            "x = {}",
+            position.x
+        );
+        assert!(
+            (position.z - 3.8).abs() < 0.01,
+            "stopped at z = {}",
+            position.z
+        );
+        // Velocity into the wall is gone, along it is kept
+        assert_eq!(body.movement_velocity.x, 0.0);
+        assert!(body.movement_velocity.y > 0.0);
+    }
+
+    #[test]
+    fn no_tunnelling_through_ground_and_ceiling() {
+        let room = walled_room();
+        let mut body = test_body(Vec3::new(2.5, 0.0, 4.5));
+        body.can_fly = true;
+        body.input_state.fly_up = true;
+        body.jump_strength = 1.0e6;
+        body.update_physics(1.0, &room);
+        assert_eq!(body.feet_position.y, 4.0 - body.height);
+
+        body.input_state = InputState {
+            fly_down: true,
+            ..Default::default()
+        };
+        body.update_physics(1.0, &room);
+        assert_eq!(body.feet_position.y, 0.0);
+    }
+
+    #[test]
+    fn steps_up_only_low_steps() {
+        // Low step at `x` = 5 and a high step at `x` = 8
+        let room = test_room(|x, tile| {
+            tile.ground_height = match x {
+                ..5 => 0.0,
+                5..8 => 0.3,
+                _ => 0.8,
+            }
+        });
+        let mut body = test_body(Vec3::new(2.5, 0.0, 4.5));
+        body.update_physics(0.1, &room);
+        assert!(body.is_grounded);
+
+        // Walk over the low step until the high one blocks the way
+        body.movement_velocity = Vec2::new(100.0, 0.0);
+        body.update_physics(1.0, &room);
+        assert!((body.feet_position.x - (8.0 - body.radius)).abs() < 0.01);
+        assert_eq!(body.feet_position.y, 0.3);
+        assert!(body.is_grounded);
+        // The view follows the step smoothly
+        let eye_height = body.eye_height;
+        assert!((body.get_target_data().origin.y - eye_height).abs() < 1e-5);
+        for _ in 0..100 {
+            body.update_physics(0.1, &room);
+        }
+        assert!((body.get_target_data().origin.y - (0.3 + eye_height)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn snaps_down_steps() {
+        // Step at `x` = 5
+        let room = test_room(|x, tile| {
+            if x >= 5 {
+                tile.ground_height = 0.3;
+            }
+        });
+        let mut body = test_body(Vec3::new(5.3, 0.3, 4.5));
+        body.update_physics(0.1, &room);
+        assert!(body.is_grounded);
+
+        // Short frame, so gravity alone wouldn't pull the body down the step
+        body.movement_velocity = Vec2::new(-50.0, 0.0);
+        body.update_physics(0.01, &room);
+        assert!(body.feet_position.x < 5.0 - body.radius);
+        assert_eq!(body.feet_position.y, 0.0);
+        assert!(body.is_grounded);
+
+        // Jumping doesn't snap back to the ground
+        body.input_state.jump = true;
+        body.update_physics(0.01, &room);
+        assert!(body.feet_position.y > 0.0);
+        assert!(!body.is_grounded);
+    }
+
+    #[test]
+    fn walks_up_and_down_ramps() {
+        // Ramp between `x` = 5 and `x` = 9 which rises by 2
+        let room = test_room(|x, tile| {
+            tile.ground_height = (x - 5).clamp(0, 4) as f32 * 0.5;
+            if (5..9).contains(&x) {
+                tile.ground_slope = Slope::new(Vec2::X, 0.5);
+            }
+        });
+        let mut body = test_body(Vec3::new(2.5, 0.0, 4.5));
+        body.update_physics(0.1, &room);
+        let eye_height = body.eye_height;
+
+        for _ in 0..500 {
+            body.movement_velocity = Vec2::new(3.0, 0.0);
+            body.update_physics(0.01, &room);
+            let x = body.feet_position.x;
+            if (5.5..8.5).contains(&x) {
+                // Stands on the ramp right under the feet and the view keeps up
+                assert!((body.feet_position.y - (x - 5.0) * 0.5).abs() < 1e-4);
+                assert!(body.is_grounded);
+                let eye_y = body.get_target_data().origin.y;
+                assert!((eye_y - (body.feet_position.y + eye_height)).abs() < 1e-4);
+            }
+        }
+        assert!(body.feet_position.x > 9.5);
+        assert_eq!(body.feet_position.y, 2.0);
+
+        for _ in 0..500 {
+            body.movement_velocity = Vec2::new(-3.0, 0.0);
+            body.update_physics(0.01, &room);
+            let x = body.feet_position.x;
+            if (5.5..8.5).contains(&x) {
+                assert!((body.feet_position.y - (x - 5.0) * 0.5).abs() < 1e-4);
+                assert!(body.is_grounded);
+            }
+        }
+        assert!(body.feet_position.x < 4.5);
+        assert_eq!(body.feet_position.y, 0.0);
+    }
+
+    #[test]
+    fn steps_onto_tiles_behind_portals() {
+        // Portal at (10, 4) into a room twice as big,
+        // which lines up 0.5 above the ground of its portal
+        let src_portal = Portal {
+            id: PortalID(0),
+            direction: Vec2::X,
+            position: PointXZ::new(10, 4),
+            center: Vec2::new(10.5, 4.5),
+            ground_height: 0.0,
+            yaw: 0.0,
+            scale: 1.0,
+            height_offset: 0.0,
+            destination: None,
+        };
+        let dest_portal = Portal {
+            direction: Vec2::NEG_X,
+            position: PointXZ::new(1, 4),
+            center: Vec2::new(1.5, 4.5),
+            ground_height: 1.0,
+            scale: 2.0,
+            height_offset: 0.5,
+            ..src_portal
+        };
+        let back = PortalTransform::new(&dest_portal, &src_portal);
+        // The ground behind the portal is a low step of 0.3 in this room
+        let behind = test_room(|_, tile| {
+            tile.ground_height = 2.1;
+            tile.ceiling_height = 9.5;
+        })(3, 4)
+        .unwrap();
+        let room = test_room(|x, tile| {
+            if x > 10 {
+                tile.ground_height = 4.0;
+            }
+        });
+        let portal_room = |x, z| match (x, z) {
+            (10, 4) => Some(behind.through_portal(&back, PointXZ::new(10, 4))),
+            _ => room(x, z),
+        };
+
+        let mut body = test_body(Vec3::new(8.5, 0.0, 4.5));
+        body.movement_velocity = Vec2::new(100.0, 0.0);
+        body.update_physics(1.0, portal_room);
+        assert!((body.feet_position.x - (11.0 - body.radius)).abs() < 0.01);
+        assert!((body.feet_position.y - 0.3).abs() < 1e-5);
+        assert!(body.is_grounded);
+    }
+
+    #[test]
+    fn stands_on_bridges_and_walks_under_them() {
+        // Bridge over `x` = 3 and 4, with the bottom at 2.2 and the ground at 2.5
+        let tilemap = parse_test_blueprint("bridge");
+        let room = Room::new(RoomID(0), &tilemap, Vec2::Y, 0, 0, None);
+        let room = RoomRef {
+            tilemap: &tilemap,
+            data: &room,
+        };
+        let update = |body: &mut CylinderBody, delta: f32| {
+            let feet_height = body.feet_position.y;
+            body.update_physics(delta, |x, z| {
+                room.get_span_checked(x, z, feet_height)
+                    .map(Tile::platforms)
+            });
+        };
+
+        let mut body = test_body(Vec3::new(4.0, 2.5, 1.5));
+        update(&mut body, 0.1);
+        assert_eq!(body.feet_position.y, 2.5);
+        assert!(body.is_grounded);
+
+        // The bridge is high enough to walk under it to the other side
+        let mut body = test_body(Vec3::new(1.5, 0.0, 1.5));
+        body.movement_velocity = Vec2::new(100.0, 0.0);
+        update(&mut body, 1.0);
+        assert!(
+            body.feet_position.x > 5.0 + body.radius,
+            "x = {}",
+            body.feet_position.x
+        );
+        assert_eq!(body.feet_position.y, 0.0);
+        assert!(body.is_grounded);
+    }
+
+    #[test]
+    fn carried_by_moving_platforms() {
+        // Platform at `x` = 5 which moves from the ground up to 3 and back down
+        let platform_room = |height: f32| {
+            test_room(move |x, tile| {
+                tile.ceiling_height = 6.0;
+                if x == 5 {
+                    tile.ground_height = height;
+                }
+            })
+        };
+        let mut body = test_body(Vec3::new(5.5, 0.0, 4.5));
+        body.update_physics(0.01, platform_room(0.0));
+        let eye_height = body.eye_height;
+
+        for step in 0..=300 {
+            let height = step as f32 * 0.01;
+            body.update_physics(0.01, platform_room(height));
+            assert_eq!(body.feet_position.y, height);
+            assert!(body.is_grounded);
+            // The view moves together with the platform
+            let eye_y = body.get_target_data().origin.y;
+            assert!((eye_y - (height + eye_height)).abs() < 1e-4);
+        }
+        for step in (0..300).rev() {
+            let height = step as f32 * 0.01;
+            body.update_physics(0.01, platform_room(height));
+            assert_eq!(body.feet_position.y, height);
+            assert!(body.is_grounded);
+        }
+    }
+
+    #[test]
+    fn pushed_out_of_closing_doors() {
+        // Door at `x` = 10 which closes by lowering its ceiling to the ground
+        let door_room = |ceiling_height: f32| {
+            test_room(move |x, tile| {
+                if x == 10 {
+                    tile.ceiling_height = ceiling_height;
+                }
+            })
+        };
+        let mut body = test_body(Vec3::new(10.4, 0.0, 4.5));
+        for step in (0..=400).rev() {
+            let ceiling_height = step as f32 * 0.01;
+            body.update_physics(0.01, door_room(ceiling_height));
+            // Stays on the ground and leaves before the door gets too low
+            assert_eq!(body.feet_position.y, 0.0);
+            if body.feet_position.x > 10.0 - body.radius {
+                let head = body.feet_position.y + body.height;
+                assert!(head <= ceiling_height + CEILING_COLLISION_OFFSET + 0.01);
+            }
+        }
+        assert!(body.feet_position.x <= 10.0 - body.radius);
+        assert_eq!(body.feet_position.y, 0.0);
+    }
+}
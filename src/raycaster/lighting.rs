@@ -0,0 +1,216 @@
+use glam::Vec3;
+
+use crate::{
+    map::tilemap::Fog,
+    textures::{TextureDataRef, Transparency},
+};
+
+use super::{ray::Ray, FrameRenderer};
+
+// Distance is in tiles
+const SPOTLIGHT_DISTANCE: f32 = 8.5;
+const SPOTLIGHT_SMOOTHED_DISTANCE: f32 = 2.5;
+const SPOTLIGHT_STRENGTH: f32 = 0.09;
+const FLASHLIGHT_INTENSITY: f32 = 1.35;
+const FLASHLIGHT_OUTER_RADIUS: f32 = 1.1;
+const FLASHLIGHT_INNER_RADIUS: f32 = 0.65;
+const FLASHLIGHT_DISTANCE: f32 = 16.0;
+
+/// Texels of masked textures with alpha below this value are skipped.
+pub const ALPHA_CUTOFF: u8 = 128;
+
+pub const NORMAL_Y_POSITIVE: Vec3 = Vec3::new(0.0, 1.0, 0.0);
+pub const NORMAL_Y_NEGATIVE: Vec3 = Vec3::new(0.0, -1.0, 0.0);
+
+/// How a drawn surface should be lit and fogged.
+#[derive(Debug, Clone, Copy)]
+pub enum Shading {
+    /// Room ambient light and the light baked into the tile face together
+    /// with the player spotlight and flashlight which both fade out
+    /// with the distance. Fog thickens with the distance of the surface.
+    Lit { ambient: f32, baked: Vec3, fog: Fog },
+    /// Only the room ambient light. Used for the skybox since it is
+    /// infinitely far away and no other light can reach it.
+    /// Fog is applied as if the skybox was `fog_distance` tiles away.
+    Ambient {
+        ambient: f32,
+        fog: Fog,
+        fog_distance: f32,
+    },
+}
+
+/// Light values of a surface which stay the same along the whole
+/// drawn segment, so only the flashlight cone has to be calculated per pixel.
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceLight {
+    ambient: f32,
+    baked: Vec3,
+    spotlight: f32,
+    flashlight: f32,
+    flashlight_x_squared: f32,
+    /// Fog color with channels in range [0.0, 255.0].
+    fog_color: Vec3,
+    fog_factor: f32,
+}
+
+impl<'a> FrameRenderer<'a> {
+    /// Calculates the light for a surface point at `distance` tiles from the
+    /// ray origin. `view_dir` points from the eye towards the surface point
+    /// and `normal` is the normal of the surface.
+    #[inline]
+    pub(super) fn surface_light(
+        &self,
+        ray: &Ray,
+        shading: Shading,
+        distance: f32,
+        view_dir: Vec3,
+        normal: Vec3,
+    ) -> SurfaceLight {
+        let (ambient, baked, fog) = match shading {
+            Shading::Lit {
+                ambient,
+                baked,
+                fog,
+            } => (ambient, baked, fog),
+            Shading::Ambient {
+                ambient,
+                fog,
+                fog_distance,
+            } => {
+                return SurfaceLight {
+                    ambient,
+                    baked: Vec3::ZERO,
+                    spotlight: 0.0,
+                    flashlight: 0.0,
+                    flashlight_x_squared: 0.0,
+                    fog_color: fog.color * 255.0,
+                    fog_factor: fog.factor(fog_distance),
+                }
+            }
+        };
+
+        let spotlight = ((SPOTLIGHT_DISTANCE - distance) / SPOTLIGHT_SMOOTHED_DISTANCE)
+            .clamp(0.0, 1.0)
+            * SPOTLIGHT_STRENGTH;
+
+        let flashlight = if self.use_flashlight {
+            let diffuse = (-view_dir).normalize().dot(normal).max(0.0);
+            // Smooth out the flashlight intensity using the distance
+            (1.0 - (distance / FLASHLIGHT_DISTANCE).clamp(0.0, 1.0))
+                * FLASHLIGHT_INTENSITY
+                * diffuse
+        } else {
+            0.0
+        };
+        // Multiply by the canvas aspect ratio so the light has a shape of a circle.
+        let flashlight_x = ray.plane_x * self.view_aspect;
+
+        SurfaceLight {
+            ambient,
+            baked,
+            spotlight,
+            flashlight,
+            flashlight_x_squared: flashlight_x * flashlight_x,
+            fog_color: fog.color * 255.0,
+            fog_factor: fog.factor(distance),
+        }
+    }
+
+    /// Writes the lit and fogged `color` into the `pixel` in row `y`.
+    #[inline]
+    pub(super) fn shade_pixel(
+        &self,
+        pixel: &mut [u8],
+        color: &[u8],
+        light: SurfaceLight,
+        y: usize,
+    ) {
+        let light_color = self.light_at(light, y);
+        let lit = Vec3::new(
+            color[0] as f32 * light_color.x,
+            color[1] as f32 * light_color.y,
+            color[2] as f32 * light_color.z,
+        );
+        let shaded = if light.fog_factor > 0.0 {
+            lit.min(Vec3::splat(255.0))
+                .lerp(light.fog_color, light.fog_factor)
+        } else {
+            lit
+        };
+        pixel[0] = shaded.x as u8;
+        pixel[1] = shaded.y as u8;
+        pixel[2] = shaded.z as u8;
+    }
+
+    /// Writes the texel starting at index `i` of the `texture` into the `pixel`
+    /// in row `y`. Transparent texels are skipped or blended over the pixel.
+    #[inline]
+    pub(super) fn shade_texel(
+        &self,
+        pixel: &mut [u8],
+        texture: &TextureDataRef,
+        i: usize,
+        light: SurfaceLight,
+        y: usize,
+    ) {
+        let texel = &texture.data[i..i + 4];
+        self.shade_rgba(pixel, texel, texture.transparency, light, y)
+    }
+
+    /// Writes the RGBA `texel` of a texture with the `transparency` into the
+    /// `pixel` in row `y`. Transparent texels are skipped or blended over the pixel.
+    #[inline]
+    pub(super) fn shade_rgba(
+        &self,
+        pixel: &mut [u8],
+        texel: &[u8],
+        transparency: Transparency,
+        light: SurfaceLight,
+        y: usize,
+    ) {
+        let color = &texel[..3];
+        match transparency {
+            Transparency::Opaque => self.shade_pixel(pixel, color, light, y),
+            Transparency::Masked => {
+                if texel[3] >= ALPHA_CUTOFF {
+                    self.shade_pixel(pixel, color, light, y)
+                }
+            }
+            Transparency::Translucent => {
+                let alpha = texel[3];
+                if alpha != 0 {
+                    let mut shaded = [0; 3];
+                    self.shade_pixel(&mut shaded, color, light, y);
+                    blend_pixel(pixel, &shaded, alpha);
+                }
+            }
+        }
+    }
+
+    /// Returns the final light color of the surface pixel in row `y`.
+    #[inline]
+    fn light_at(&self, light: SurfaceLight, y: usize) -> Vec3 {
+        if light.flashlight <= 0.0 {
+            return light.baked + (light.ambient + light.spotlight);
+        }
+        let flashlight_y = 2.0 * y as f32 * self.height_recip - 1.0;
+        let flashlight_radius =
+            (light.flashlight_x_squared + flashlight_y * flashlight_y).sqrt();
+        let t = 1.0
+            - ((flashlight_radius - FLASHLIGHT_INNER_RADIUS)
+                / (FLASHLIGHT_OUTER_RADIUS - FLASHLIGHT_INNER_RADIUS))
+                .clamp(0.0, 1.0);
+        let flashlight = t * t * (3.0 - t * 2.0) * light.flashlight;
+
+        light.baked + (light.ambient + flashlight.max(light.spotlight))
+    }
+}
+
+/// Blends the `color` over the `pixel` using the provided `alpha`.
+#[inline]
+fn blend_pixel(pixel: &mut [u8], color: &[u8], alpha: u8) {
+    let alpha = alpha as u16;
+    pixel.iter_mut().zip(color).for_each(|(dest, &src)| {
+        *dest = ((src as u16 * alpha + *dest as u16 * (255 - alpha)) / 255) as u8;
+    });
+}
@@ -1,20 +1,31 @@
 pub mod camera;
+mod lighting;
+mod object;
 mod platform;
 mod ray;
+mod sprite;
 mod wall;
 
 use glam::{Vec2, Vec3};
+use lighting::Shading;
+use object::ObjectRenderParams;
 use platform::{PlatformRenderParams, PlatformType};
 use ray::WallSide;
 use rayon::iter::{IndexedParallelIterator, ParallelIterator};
 use rayon::slice::ParallelSliceMut;
+use sprite::SpriteRenderParams;
 use wall::WallRenderParams;
 
-use crate::map::portal::Orientation;
+use crate::map::portal::PortalTransform;
+use crate::map::room::{RoomID, RoomRef};
+use crate::map::tilemap::{Fog, ObjectID, Slope, TextureMapping};
 use crate::map::Map;
+use crate::models::ModelArray;
 use crate::player::Player;
 use crate::raycaster::camera::Camera;
-use crate::textures::{SkyboxTexturesRef, TextureArray, TextureDataRef, TextureID};
+use crate::textures::{
+    SkyboxTexturesRef, TextureArray, TextureDataRef, TextureFilter, TextureID,
+};
 
 use self::ray::Ray;
 
@@ -35,12 +46,19 @@ pub struct FrameRenderer<'a> {
     player: &'a Player,
     map: &'a Map,
     textures: &'a TextureArray,
+    models: &'a ModelArray,
+
+    use_flashlight: bool,
+    /// Filter for textures drawn at their full size.
+    texture_filter: TextureFilter,
 
     // Frequently used values
     y_shearing: f32,
     view_height: u32,
     half_view_height: f32,
     width_recip: f32,
+    height_recip: f32,
+    view_aspect: f32,
 }
 
 impl<'a> FrameRenderer<'a> {
@@ -49,55 +67,103 @@ impl<'a> FrameRenderer<'a> {
         player: &'a Player,
         map: &'a Map,
         textures: &'a TextureArray,
+        models: &'a ModelArray,
+        texture_filter: TextureFilter,
     ) -> Self {
         Self {
             camera,
             player,
             map,
             textures,
+            models,
+
+            use_flashlight: player.use_flashlight(),
+            texture_filter,
 
             y_shearing: camera.y_shearing,
             view_height: camera.view_height,
             half_view_height: camera.view_height as f32 * 0.5,
             width_recip: 1.0 / camera.view_width as f32,
+            height_recip: 1.0 / camera.view_height as f32,
+            view_aspect: camera.view_aspect,
         }
     }
 
-    pub fn render_par(&mut self, pixel_buffer: &'a mut [u8]) {
+    /// Returns all rooms which were seen in the frame.
+    pub fn render_par(&mut self, pixel_buffer: &'a mut [u8]) -> SeenRooms {
         // TODO is there better multithreading?
-        pixel_buffer
+        let mut seen = pixel_buffer
             .par_chunks_exact_mut(self.camera.view_height as usize * 3)
             .enumerate()
-            .for_each(|(column_index, column)| {
-                self.render_column(column_index, column);
+            .fold(
+                || (ColumnBuffers::default(), SeenRooms::default()),
+                |(mut buffers, mut seen), (column_index, column)| {
+                    self.render_column(column_index, column, &mut buffers, &mut seen);
+                    (buffers, seen)
+                },
+            )
+            .map(|(_, seen)| seen)
+            .reduce(SeenRooms::default, |mut seen, other| {
+                seen.rooms.extend(other.rooms);
+                seen.dead_ends.extend(other.dead_ends);
+                seen
             });
+        seen.sort();
+        seen
     }
 
-    pub fn render(&mut self, pixel_buffer: &'a mut [u8]) {
-        for (column_index, column) in pixel_buffer
+    /// Returns all rooms which were seen in the frame.
+    pub fn render(&mut self, pixel_buffer: &'a mut [u8]) -> SeenRooms {
+        let mut buffers = ColumnBuffers::default();
+        let mut seen = SeenRooms::default();
+        pixel_buffer
             .chunks_exact_mut(self.camera.view_height as usize * 3)
             .enumerate()
-        {
-            self.render_column(column_index, column);
-        }
+            .for_each(|(column_index, column)| {
+                self.render_column(column_index, column, &mut buffers, &mut seen)
+            });
+        seen.sort();
+        seen
     }
 
     // TODO maybe draw first the floor, then bottom wall, then top wall, then ceiling
-    fn render_column(&self, column_index: usize, column: &mut [u8]) {
+    /// Adds the rooms through which the ray passed to `seen`.
+    fn render_column(
+        &self,
+        column_index: usize,
+        column: &mut [u8],
+        buffers: &mut ColumnBuffers<'a>,
+        seen: &mut SeenRooms,
+    ) {
         let mut ray = Ray::new(self.camera, self.camera.origin, column_index);
         let static_ray = Ray::new_one_step(self.camera, Vec3::splat(0.5), column_index);
 
         let mut current_room = self.map.get_room_data(self.player.current_room_id());
         let mut current_room_dimensions = current_room.tilemap.dimensions_i64();
+        seen.add_room(current_room.data.id());
 
         let room_direction = current_room.data.direction;
 
         let mut skybox_textures = self
             .textures
             .get_skybox_textures(current_room.data.skybox());
+        let mut ambient = current_room.data.ambient_light_intensity();
+        let mut fog = current_room.data.fog();
 
         let mut top_draw_bound = self.view_height as usize;
         let mut bottom_draw_bound = 0;
+        let ColumnBuffers {
+            depth,
+            deferred,
+            queued_objects,
+        } = buffers;
+        depth.clear();
+        depth.resize(self.view_height as usize, f32::INFINITY);
+        deferred.clear();
+        queued_objects.clear();
+        // Distance and draw bounds at which the ray entered the current room
+        let mut room_enter_dist = 0.0;
+        let (mut room_bottom_bound, mut room_top_bound) = (bottom_draw_bound, top_draw_bound);
 
         // DDA loop
         loop {
@@ -133,33 +199,84 @@ impl<'a> FrameRenderer<'a> {
              *           Drawing platforms (floor and ceiling)
              * ========================================================== */
             // Tile which the ray just traveled over before hitting a wall
-            let current_tile = current_room
+            let current_tile =
+                current_room.get_tile_unchecked(current_tile_x, current_tile_z);
+            let current_tile_light = current_room
                 .tilemap
-                .get_tile_unchecked(current_tile_x, current_tile_z);
+                .get_tile_light_unchecked(current_tile_x, current_tile_z);
+
+            // Models are drawn last since they can cover what is behind them.
+            // Bigger models cover more tiles, but are queued only once per room.
+            for &id in &current_tile.objects {
+                if let (Some(object), false) =
+                    (current_room.get_object(id), queued_objects.contains(&id))
+                {
+                    queued_objects.push(id);
+                    deferred.push(DeferredRender::Object(ObjectRenderParams {
+                        ray,
+                        bottom_draw_bound,
+                        top_draw_bound,
+                        object,
+                        model: self.models.get_model_data(object.model),
+                        shading: Shading::Lit {
+                            ambient,
+                            baked: current_tile_light.ground,
+                            fog,
+                        },
+                    }));
+                }
+            }
+
+            self.queue_spans(
+                ray,
+                &current_room,
+                PointXZ::new(current_tile_x, current_tile_z),
+                bottom_draw_bound,
+                top_draw_bound,
+                ambient,
+                fog,
+                deferred,
+            );
 
             // Draw ground platform
             let params = PlatformRenderParams {
                 ray,
                 bottom_draw_bound,
                 top_draw_bound,
-                height: current_tile.ground_height,
+                height: current_tile.ground_height_at(ray.origin.x, ray.origin.z),
+                slope: current_tile.ground_slope,
                 platform_type: PlatformType::Floor,
                 texture: self.textures.get_texture_data(current_tile.ground_tex),
+                mapping: current_tile.ground_mapping,
+                shading: Shading::Lit {
+                    ambient,
+                    baked: current_tile_light.ground,
+                    fog,
+                },
             };
 
-            let (from, drawn_to) = self.render_platform(params, column);
+            let (from, drawn_to) =
+                self.render_platform(params, column, Some(depth.as_mut_slice()));
 
             // Draw ceiling platform
             let params = PlatformRenderParams {
                 ray,
                 bottom_draw_bound,
                 top_draw_bound,
-                height: current_tile.ceiling_height,
+                height: current_tile.ceiling_height_at(ray.origin.x, ray.origin.z),
+                slope: current_tile.ceiling_slope,
                 platform_type: PlatformType::Ceiling,
                 texture: self.textures.get_texture_data(current_tile.ceiling_tex),
+                mapping: current_tile.ceiling_mapping,
+                shading: Shading::Lit {
+                    ambient,
+                    baked: current_tile_light.ceiling,
+                    fog,
+                },
             };
 
-            let (drawn_from, to) = self.render_platform(params, column);
+            let (drawn_from, to) =
+                self.render_platform(params, column, Some(depth.as_mut_slice()));
             if from != bottom_draw_bound {
                 //println!("floor skiped!");
                 fill_color(column, bottom_draw_bound, from, 200);
@@ -177,36 +294,73 @@ impl<'a> FrameRenderer<'a> {
              * ========================================================== */
             // The tile ray hit
             let next_tile = current_room
-                .tilemap
                 .get_tile_unchecked(ray.next_tile.x as usize, ray.next_tile.z as usize);
+            let next_tile_light = current_room
+                .tilemap
+                .get_tile_light_unchecked(ray.next_tile.x as usize, ray.next_tile.z as usize);
+            let wall_normal = ray.wall_normal();
+            // Sloped platforms meet the walls at a different height along the wall
+            let hit_x = ray.origin.x + ray.wall_dist * ray.dir.x;
+            let hit_z = ray.origin.z + ray.wall_dist * ray.dir.z;
 
             let params = WallRenderParams {
                 ray,
                 bottom_draw_bound,
                 top_draw_bound,
                 bottom_level: next_tile.bottom_height,
-                top_level: next_tile.ground_height,
-                texture: self.textures.get_texture_data(next_tile.bottom_wall_tex),
+                top_level: next_tile.ground_height_at(hit_x, hit_z),
+                texture: self
+                    .textures
+                    .get_texture_data(next_tile.bottom_wall_tex(wall_normal)),
+                mapping: next_tile.bottom_wall_mapping,
+                shading: Shading::Lit {
+                    ambient,
+                    baked: next_tile_light.bottom_wall(wall_normal),
+                    fog,
+                },
             };
 
-            // Draw bottom wall
-            let (from, drawn_to) = self.render_wall(params, column);
+            // Draw bottom wall, see-through walls are drawn after everything behind them
+            let (from, drawn_to) = if params.texture.transparency.is_opaque() {
+                self.render_wall(params, column, Some(depth.as_mut_slice()))
+            } else {
+                deferred.push(DeferredRender::Wall(params));
+                (bottom_draw_bound, bottom_draw_bound)
+            };
             let params = WallRenderParams {
                 ray,
                 bottom_draw_bound,
                 top_draw_bound,
-                bottom_level: next_tile.ceiling_height,
+                bottom_level: next_tile.ceiling_height_at(hit_x, hit_z),
                 top_level: next_tile.top_height,
-                texture: self.textures.get_texture_data(next_tile.bottom_wall_tex),
+                texture: self
+                    .textures
+                    .get_texture_data(next_tile.top_wall_tex(wall_normal)),
+                mapping: next_tile.top_wall_mapping,
+                shading: Shading::Lit {
+                    ambient,
+                    baked: next_tile_light.top_wall(wall_normal),
+                    fog,
+                },
             };
             // Draw top wall
-            let (drawn_from, to) = self.render_wall(params, column);
+            let (drawn_from, to) = if params.texture.transparency.is_opaque() {
+                self.render_wall(params, column, Some(depth.as_mut_slice()))
+            } else {
+                deferred.push(DeferredRender::Wall(params));
+                (top_draw_bound, top_draw_bound)
+            };
             if from != bottom_draw_bound {
                 //println!("wall bottom skiped!");
                 self.render_skybox(
                     static_ray,
                     room_direction,
                     skybox_textures,
+                    Shading::Ambient {
+                        ambient,
+                        fog,
+                        fog_distance: ray.wall_dist,
+                    },
                     bottom_draw_bound,
                     from,
                     column,
@@ -228,31 +382,51 @@ impl<'a> FrameRenderer<'a> {
                 let src_portal = current_room.get_portal(id);
                 match src_portal.destination {
                     Some((room_id, dest_id)) => {
+                        self.collect_sprites(
+                            &ray,
+                            &current_room,
+                            room_enter_dist,
+                            ray.wall_dist,
+                            room_bottom_bound,
+                            room_top_bound,
+                            deferred,
+                        );
+                        room_enter_dist = ray.wall_dist;
+                        queued_objects.clear();
+                        (room_bottom_bound, room_top_bound) = (bottom_draw_bound, top_draw_bound);
+
                         let dest_room = self.map.get_room_data(room_id);
-                        //dest_room.data.direction
                         let dest_portal = dest_room.get_portal(dest_id);
-                        let src_angle = f32::atan2(src_portal.direction.y, src_portal.direction.x);
-                        let dest_angle = f32::atan2(-dest_portal.direction.y, -dest_portal.direction.x);
-                        let diff = dest_angle - src_angle;
-                        //panic!("diff: {}", diff);
-
-                        //let rotation = src_portal.direction_difference(&dest_portal);
-                        ray.rotate(diff);
-
-                        let offset = Vec2::new(ray.origin.x, ray.origin.z) - src_portal.center;
-                        let rotation = glam::mat2(Vec2::new(diff.cos(), diff.sin()), Vec2::new(-diff.sin(), diff.cos()));
-                        let rotated_offset = rotation * offset;
-                        let new_position = dest_portal.center + rotated_offset + (-dest_portal.direction);
-                        ray.origin = Vec3::new(new_position.x, ray.origin.y + dest_portal.ground_height - src_portal.ground_height, new_position.y);
+                        ray.pass_portal(&PortalTransform::new(&src_portal, &dest_portal));
+                        let (width, depth) = dest_room.tilemap.dimensions_i64();
+                        if !(0..width).contains(&ray.next_tile.x)
+                            || !(0..depth).contains(&ray.next_tile.z)
+                        {
+                            break;
+                        }
 
                         current_room = dest_room;
                         current_room_dimensions = current_room.tilemap.dimensions_i64();
+                        seen.add_room(room_id);
                         skybox_textures = self
                             .textures
                             .get_skybox_textures(current_room.data.skybox());
+                        ambient = current_room.data.ambient_light_intensity();
+                        fog = current_room.data.fog();
                     }
                     None => {
                         fill_color(column, bottom_draw_bound, top_draw_bound, 0);
+                        self.collect_sprites(
+                            &ray,
+                            &current_room,
+                            room_enter_dist,
+                            ray.wall_dist,
+                            room_bottom_bound,
+                            room_top_bound,
+                            deferred,
+                        );
+                        self.render_deferred(deferred, depth, column);
+                        seen.dead_ends.push(current_room.data.id());
                         return;
                     }
                 }
@@ -264,17 +438,200 @@ impl<'a> FrameRenderer<'a> {
             static_ray,
             room_direction,
             skybox_textures,
+            Shading::Ambient {
+                ambient,
+                fog,
+                fog_distance: ray.wall_dist,
+            },
             bottom_draw_bound,
             top_draw_bound,
             column,
         );
+        self.collect_sprites(
+            &ray,
+            &current_room,
+            room_enter_dist,
+            f32::INFINITY,
+            room_bottom_bound,
+            room_top_bound,
+            deferred,
+        );
+        self.render_deferred(deferred, depth, column);
+    }
+
+    /// Queues the platforms of the spans which the other tile layers stack on
+    /// the `tile` the ray passed over and the walls of the spans on the tile it
+    /// hit. The ray can pass above and below the spans, so instead of narrowing
+    /// the draw bounds they are drawn last, only where nothing closer was drawn.
+    /// Faces which would fall outside of the draw bounds are not queued.
+    #[allow(clippy::too_many_arguments)]
+    fn queue_spans(
+        &self,
+        ray: Ray,
+        room: &RoomRef,
+        tile: PointXZ<usize>,
+        bottom_draw_bound: usize,
+        top_draw_bound: usize,
+        ambient: f32,
+        fog: Fog,
+        deferred: &mut Vec<DeferredRender<'a>>,
+    ) {
+        // Same rows as the platforms and walls would be drawn between
+        let pixel_height_at = |distance: f32, height: f32| {
+            self.half_view_height
+                + self.half_view_height / distance * (height - ray.origin.y)
+                + self.y_shearing
+        };
+        let is_drawn = |from: f32, to: f32| {
+            let draw_from = (from as usize).clamp(bottom_draw_bound, top_draw_bound);
+            (to as usize).clamp(draw_from, top_draw_bound) > draw_from
+        };
+
+        let (x, z) = (ray.origin.x, ray.origin.z);
+        for (span, light) in room.tilemap.get_lit_spans_unchecked(tile.x, tile.z) {
+            // Solid parts of the span are also closed from below and above,
+            // like with a ceiling and a ground
+            let faces = [
+                (
+                    PlatformType::Floor,
+                    span.ground_height_at(x, z),
+                    span.ground_slope,
+                    span.ground_tex,
+                    span.ground_mapping,
+                    light.ground,
+                ),
+                (
+                    PlatformType::Ceiling,
+                    span.ceiling_height_at(x, z),
+                    span.ceiling_slope,
+                    span.ceiling_tex,
+                    span.ceiling_mapping,
+                    light.ceiling,
+                ),
+                (
+                    PlatformType::Ceiling,
+                    span.bottom_height,
+                    Slope::default(),
+                    span.ceiling_tex,
+                    span.ceiling_mapping,
+                    light.bottom,
+                ),
+                (
+                    PlatformType::Floor,
+                    span.top_height,
+                    Slope::default(),
+                    span.ground_tex,
+                    span.ground_mapping,
+                    light.top,
+                ),
+            ];
+            for (platform_type, height, slope, texture, mapping, baked) in faces {
+                // Sloped platforms change height along the ray, so they are always queued
+                let near = pixel_height_at(ray.previous_wall_dist, height);
+                let far = pixel_height_at(ray.wall_dist, height);
+                let (from, to) = match platform_type {
+                    PlatformType::Floor => (near, far),
+                    PlatformType::Ceiling => (far, near),
+                };
+                if slope.is_flat() && !is_drawn(from, to) {
+                    continue;
+                }
+                deferred.push(DeferredRender::Platform(PlatformRenderParams {
+                    ray,
+                    bottom_draw_bound,
+                    top_draw_bound,
+                    height,
+                    slope,
+                    platform_type,
+                    texture: self.textures.get_texture_data(texture),
+                    mapping,
+                    shading: Shading::Lit {
+                        ambient,
+                        baked,
+                        fog,
+                    },
+                }));
+            }
+        }
+
+        let (next_x, next_z) = (ray.next_tile.x as usize, ray.next_tile.z as usize);
+        let wall_normal = ray.wall_normal();
+        let hit_x = ray.origin.x + ray.wall_dist * ray.dir.x;
+        let hit_z = ray.origin.z + ray.wall_dist * ray.dir.z;
+        for (span, light) in room.tilemap.get_lit_spans_unchecked(next_x, next_z) {
+            let walls = [
+                (
+                    span.bottom_height,
+                    span.ground_height_at(hit_x, hit_z),
+                    span.bottom_wall_tex(wall_normal),
+                    span.bottom_wall_mapping,
+                    light.bottom_wall(wall_normal),
+                ),
+                (
+                    span.ceiling_height_at(hit_x, hit_z),
+                    span.top_height,
+                    span.top_wall_tex(wall_normal),
+                    span.top_wall_mapping,
+                    light.top_wall(wall_normal),
+                ),
+            ];
+            for (bottom_level, top_level, texture, mapping, baked) in walls {
+                let from = pixel_height_at(ray.wall_dist, bottom_level);
+                let to = pixel_height_at(ray.wall_dist, top_level);
+                if !is_drawn(from, to) {
+                    continue;
+                }
+                deferred.push(DeferredRender::Wall(WallRenderParams {
+                    ray,
+                    bottom_draw_bound,
+                    top_draw_bound,
+                    bottom_level,
+                    top_level,
+                    texture: self.textures.get_texture_data(texture),
+                    mapping,
+                    shading: Shading::Lit {
+                        ambient,
+                        baked,
+                        fog,
+                    },
+                }));
+            }
+        }
     }
 
+    /// Draws the see-through walls, stacked spans, sprites and models from the
+    /// furthest to the closest one, over everything behind them. Each one keeps
+    /// the draw bounds from the moment it was hit, so it stays hidden behind closer
+    /// walls and platforms, and they all use the `depth` of each pixel.
+    fn render_deferred(
+        &self,
+        deferred: &mut Vec<DeferredRender>,
+        depth: &mut [f32],
+        column: &mut [u8],
+    ) {
+        // Stable sort keeps the order in which walls at the same distance were hit
+        deferred.sort_by(|a, b| b.distance().total_cmp(&a.distance()));
+        for render in deferred.drain(..) {
+            match render {
+                DeferredRender::Wall(params) => {
+                    self.render_wall(params, column, Some(depth));
+                }
+                DeferredRender::Platform(params) => {
+                    self.render_platform(params, column, Some(depth));
+                }
+                DeferredRender::Sprite(params) => self.render_sprite(params, depth, column),
+                DeferredRender::Object(params) => self.render_object(params, depth, column),
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn render_skybox(
         &self,
         ray: Ray,
         room_direction: Vec2,
         skybox_textures: SkyboxTexturesRef,
+        shading: Shading,
         bottom_draw_bound: usize,
         top_draw_bound: usize,
         column: &mut [u8],
@@ -293,33 +650,106 @@ impl<'a> FrameRenderer<'a> {
             bottom_level: -0.5,
             top_level: 1.5,
             texture: wall_texture,
+            mapping: TextureMapping::default(),
+            shading,
         };
 
-        self.render_wall(params, column);
+        self.render_wall(params, column, None);
 
         let params = PlatformRenderParams {
             ray,
             bottom_draw_bound,
             top_draw_bound,
             height: -0.5,
+            slope: Slope::default(),
             platform_type: PlatformType::Floor,
             texture: skybox_textures.bottom,
+            mapping: TextureMapping::default(),
+            shading,
         };
 
         // Draw ground platform
-        self.render_platform(params, column);
+        self.render_platform(params, column, None);
 
         let params = PlatformRenderParams {
             ray,
             bottom_draw_bound,
             top_draw_bound,
             height: 1.5,
+            slope: Slope::default(),
             platform_type: PlatformType::Ceiling,
             texture: skybox_textures.top,
+            mapping: TextureMapping::default(),
+            shading,
         };
 
         // Draw ceiling platform
-        self.render_platform(params, column);
+        self.render_platform(params, column, None);
+    }
+}
+
+/// Rooms through which the rays passed in a frame.
+#[derive(Debug, Default)]
+pub struct SeenRooms {
+    /// All seen rooms, sorted by their IDs.
+    pub rooms: Vec<RoomID>,
+    /// Seen rooms of which an unlinked portal was hit, sorted by their IDs.
+    pub dead_ends: Vec<RoomID>,
+}
+
+impl SeenRooms {
+    fn add_room(&mut self, room_id: RoomID) {
+        // Only a few rooms are seen, so searching is fast enough
+        if !self.rooms.contains(&room_id) {
+            self.rooms.push(room_id);
+        }
+    }
+
+    /// Columns find the rooms in no particular order.
+    fn sort(&mut self) {
+        for rooms in [&mut self.rooms, &mut self.dead_ends] {
+            rooms.sort_unstable_by_key(|room_id| room_id.0);
+            rooms.dedup();
+        }
+    }
+}
+
+/// Buffers used while rendering a column, which are kept
+/// and cleared for the next column instead of allocated again.
+#[derive(Default)]
+struct ColumnBuffers<'a> {
+    /// Distance of the closest opaque surface drawn in each pixel, in the
+    /// units of the camera's room so surfaces behind portals can be compared.
+    depth: Vec<f32>,
+    /// See-through walls, sprites and models which are drawn last.
+    deferred: Vec<DeferredRender<'a>>,
+    /// Models of the current room which are already in `deferred`.
+    queued_objects: Vec<ObjectID>,
+}
+
+/// Everything which has to be drawn after the opaque walls and platforms.
+enum DeferredRender<'a> {
+    Wall(WallRenderParams<'a>),
+    Platform(PlatformRenderParams<'a>),
+    Sprite(SpriteRenderParams<'a>),
+    Object(ObjectRenderParams<'a>),
+}
+
+impl DeferredRender<'_> {
+    /// Distance by which the draws are ordered.
+    fn distance(&self) -> f32 {
+        match self {
+            DeferredRender::Wall(params) => params.ray.camera_distance(params.ray.wall_dist),
+            // Ordered by the far edge, so a platform is drawn after the
+            // see-through walls at its far edge, which it is in front of
+            DeferredRender::Platform(params) => {
+                params.ray.camera_distance(params.ray.wall_dist)
+            }
+            DeferredRender::Sprite(params) => params.ray.camera_distance(params.distance),
+            DeferredRender::Object(params) => {
+                params.ray.camera_distance(params.ray.previous_wall_dist)
+            }
+        }
     }
 }
 
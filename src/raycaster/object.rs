@@ -0,0 +1,186 @@
+use glam::Vec3;
+
+use crate::{
+    map::tilemap::Object,
+    models::{ModelDataRef, VOXEL_HEIGHT_SCALE},
+};
+
+use super::{lighting::Shading, ray::Ray, FrameRenderer};
+
+/// Distance used instead of zero when the ray starts inside of the model.
+const MIN_PROJECTION_DISTANCE: f32 = 0.01;
+
+impl<'a> FrameRenderer<'a> {
+    /// Ray-marches the voxels of the model through each pixel of the column in
+    /// which the model is visible. Voxels are drawn only over the pixels
+    /// which are further away and their distance is written into the `depth`,
+    /// which holds the distances in the room of the camera.
+    pub(super) fn render_object(
+        &self,
+        params: ObjectRenderParams,
+        depth: &mut [f32],
+        column: &mut [u8],
+    ) {
+        let ray = params.ray;
+        let object = params.object;
+        let model = params.model;
+        let voxel_scale = Vec3::new(
+            object.voxel_size,
+            object.voxel_size * VOXEL_HEIGHT_SCALE,
+            object.voxel_size,
+        );
+        let model_size =
+            Vec3::new(model.width as f32, model.height as f32, model.depth as f32)
+                * voxel_scale;
+
+        // Limit the drawn pixels to the ones the model bounds can cover
+        let t0 = (object.position - ray.origin) / ray.dir;
+        let t1 = (object.position + model_size - ray.origin) / ray.dir;
+        let enter_dist = t0.x.min(t1.x).max(t0.z.min(t1.z));
+        let exit_dist = t0.x.max(t1.x).min(t0.z.max(t1.z));
+        if exit_dist <= enter_dist.max(0.0) {
+            return;
+        }
+        let enter_dist = enter_dist.max(MIN_PROJECTION_DISTANCE);
+        let pixel_height_at = |distance: f32, height: f32| {
+            self.half_view_height
+                + self.half_view_height / distance * (height - ray.origin.y)
+                + self.y_shearing
+        };
+        let bottom = object.position.y;
+        let top = object.position.y + model_size.y;
+        let draw_from = (pixel_height_at(enter_dist, bottom)
+            .min(pixel_height_at(exit_dist, bottom)) as usize)
+            .clamp(params.bottom_draw_bound, params.top_draw_bound);
+        let draw_to = (pixel_height_at(enter_dist, top)
+            .max(pixel_height_at(exit_dist, top)) as usize
+            + 1)
+        .clamp(draw_from, params.top_draw_bound);
+
+        // Voxel space, where each voxel is a unit cube and the model
+        // starts at the origin. The distance along the ray stays the same.
+        let origin = (ray.origin - object.position) / voxel_scale;
+
+        let blueprint = column
+            .chunks_exact_mut(3)
+            .zip(depth.iter_mut())
+            .enumerate()
+            .skip(draw_from)
+            .take(draw_to - draw_from);
+
+        for (y, (pixel, pixel_depth)) in blueprint {
+            // Vertical direction of the ray which passes through this pixel
+            let slope = (y as f32 + 0.5 - self.half_view_height - self.y_shearing)
+                / self.half_view_height;
+            let view_dir = Vec3::new(ray.dir.x, slope, ray.dir.z);
+            let dir = view_dir / voxel_scale;
+
+            // Marching happens in the room of the model
+            let max_distance = ray.room_distance(*pixel_depth);
+            let Some((distance, voxel, normal)) =
+                march_voxels(model, origin, dir, max_distance)
+            else {
+                continue;
+            };
+
+            let light =
+                self.surface_light(&ray, params.shading, distance, view_dir, normal);
+            self.shade_pixel(pixel, &[voxel.r, voxel.g, voxel.b], light, y);
+            *pixel_depth = ray.camera_distance(distance);
+        }
+    }
+}
+
+/// Walks the voxel grid along the ray and returns the distance, the color
+/// and the normal of the first hit voxel which is closer than `max_distance`.
+fn march_voxels(
+    model: ModelDataRef,
+    origin: Vec3,
+    dir: Vec3,
+    max_distance: f32,
+) -> Option<(f32, dot_vox::Color, Vec3)> {
+    let size = Vec3::new(model.width as f32, model.height as f32, model.depth as f32);
+    let dir_recip = dir.recip();
+
+    // Find where the ray enters and exits the model bounds
+    let t0 = -origin * dir_recip;
+    let t1 = (size - origin) * dir_recip;
+    let (t_min, t_max) = (t0.min(t1), t0.max(t1));
+    let mut distance = t_min.max_element().max(0.0);
+    let exit_distance = t_max.min_element().min(max_distance);
+    if distance >= exit_distance {
+        return None;
+    }
+
+    let step = dir.signum();
+    // Normal of the model side through which the ray entered
+    let mut normal = if t_min.x >= t_min.y && t_min.x >= t_min.z {
+        Vec3::new(-step.x, 0.0, 0.0)
+    } else if t_min.y >= t_min.z {
+        Vec3::new(0.0, -step.y, 0.0)
+    } else {
+        Vec3::new(0.0, 0.0, -step.z)
+    };
+
+    let entry = origin + dir * distance;
+    let mut voxel = entry.floor().clamp(Vec3::ZERO, size - 1.0);
+    let delta_dist = dir_recip.abs();
+    let mut side_dist = Vec3::new(
+        next_side_dist(entry.x, voxel.x, dir.x, delta_dist.x),
+        next_side_dist(entry.y, voxel.y, dir.y, delta_dist.y),
+        next_side_dist(entry.z, voxel.z, dir.z, delta_dist.z),
+    ) + distance;
+
+    loop {
+        if voxel.cmplt(Vec3::ZERO).any() || voxel.cmpge(size).any() {
+            return None;
+        }
+        let color = model.get_voxel(voxel.x as u32, voxel.y as u32, voxel.z as u32)?;
+        if color.a != 0 {
+            return Some((distance, color, normal));
+        }
+
+        // Step into the next voxel through the closest side
+        if side_dist.x < side_dist.y && side_dist.x < side_dist.z {
+            distance = side_dist.x;
+            voxel.x += step.x;
+            side_dist.x += delta_dist.x;
+            normal = Vec3::new(-step.x, 0.0, 0.0);
+        } else if side_dist.y < side_dist.z {
+            distance = side_dist.y;
+            voxel.y += step.y;
+            side_dist.y += delta_dist.y;
+            normal = Vec3::new(0.0, -step.y, 0.0);
+        } else {
+            distance = side_dist.z;
+            voxel.z += step.z;
+            side_dist.z += delta_dist.z;
+            normal = Vec3::new(0.0, 0.0, -step.z);
+        }
+        if distance >= exit_distance {
+            return None;
+        }
+    }
+}
+
+/// Distance along the ray from the `position` inside of the `voxel`
+/// to the next voxel side on one axis.
+#[inline]
+fn next_side_dist(position: f32, voxel: f32, dir: f32, delta_dist: f32) -> f32 {
+    if dir < 0.0 {
+        (position - voxel) * delta_dist
+    } else {
+        (voxel + 1.0 - position) * delta_dist
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ObjectRenderParams<'a> {
+    /// Ray at the moment it reached the first tile covered by the model.
+    pub ray: Ray,
+    pub bottom_draw_bound: usize,
+    pub top_draw_bound: usize,
+    pub object: Object,
+    pub model: ModelDataRef<'a>,
+    pub shading: Shading,
+}
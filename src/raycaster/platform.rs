@@ -1,23 +1,44 @@
-use crate::textures::TextureDataRef;
+use glam::Vec2;
 
-use super::{ray::Ray, FrameRenderer};
+use crate::{
+    map::tilemap::{Slope, TextureMapping},
+    textures::{mip_level, TextureDataRef, TextureFilter},
+};
+
+use super::{
+    lighting::{Shading, NORMAL_Y_NEGATIVE, NORMAL_Y_POSITIVE},
+    ray::Ray,
+    FrameRenderer,
+};
 
 impl<'a> FrameRenderer<'a> {
     pub fn render_platform(
         &self,
         params: PlatformRenderParams<'a>,
         column: &'a mut [u8],
+        mut depth: Option<&mut [f32]>,
     ) -> (usize, usize) {
         let bottom_draw_bound = params.bottom_draw_bound;
         let top_draw_bound = params.top_draw_bound;
         let ray = params.ray;
         let height = params.height;
 
-        let (texture, tex_width, tex_height) = (
-            params.texture.data,
-            params.texture.width as usize,
-            params.texture.height as usize,
-        );
+        // Variables used for reducing the amount of calculations and for optimization
+        let tile_step_factor = ray.horizontal_plane * 2.0 * self.width_recip;
+        let pos_factor = ray.camera_dir - ray.horizontal_plane
+            + tile_step_factor * ray.column_index as f32;
+        // Change of the platform height per unit of the distance along the ray
+        let rise = params
+            .slope
+            .gradient
+            .dot(Vec2::new(pos_factor.x, pos_factor.z));
+        let height_at = |distance: f32| height + rise * distance;
+
+        // Texels per pixel of a row are proportional to its distance, as the
+        // platform positions of the neighbouring columns are as much apart
+        let tex_size = params.texture.width.max(params.texture.height) as f32;
+        let mapping_scale = params.mapping.matrix.determinant().abs().sqrt();
+        let texels_per_distance = tile_step_factor.length() * mapping_scale * tex_size;
 
         let (draw_from_distance, draw_to_distance) = match params.platform_type {
             PlatformType::Floor => (ray.previous_wall_dist, ray.wall_dist),
@@ -26,53 +47,72 @@ impl<'a> FrameRenderer<'a> {
 
         // Draw from (always drawing from bottom to top):
         let half_wall_pixel_height = self.half_view_height / draw_from_distance;
-        let pixels_to_top =
-            half_wall_pixel_height * (height - ray.origin.y) + self.y_shearing;
+        let pixels_to_top = half_wall_pixel_height
+            * (height_at(draw_from_distance) - ray.origin.y)
+            + self.y_shearing;
         let draw_from = ((self.half_view_height + pixels_to_top) as usize)
             .clamp(bottom_draw_bound, top_draw_bound);
 
         // Draw to:
         let half_wall_pixel_height = self.half_view_height / draw_to_distance;
-        let pixels_to_top =
-            half_wall_pixel_height * (height - ray.origin.y) + self.y_shearing;
+        let pixels_to_top = half_wall_pixel_height
+            * (height_at(draw_to_distance) - ray.origin.y)
+            + self.y_shearing;
         let draw_to = ((self.half_view_height + pixels_to_top) as usize)
             .clamp(draw_from, top_draw_bound);
 
-        // Variables used for reducing the amount of calculations and for optimization
-        let tile_step_factor = ray.horizontal_plane * 2.0 * self.width_recip;
-        let pos_factor = ray.camera_dir - ray.horizontal_plane
-            + tile_step_factor * ray.column_index as f32;
-
         let blueprint = column
             .chunks_exact_mut(3)
             .skip(draw_from)
             .take(draw_to - draw_from);
 
         let denominator = (height - ray.origin.y) * self.half_view_height;
+        let rise_pixels = rise * self.half_view_height;
+        let normal = match params.platform_type {
+            PlatformType::Floor if params.slope.is_flat() => NORMAL_Y_POSITIVE,
+            PlatformType::Ceiling if params.slope.is_flat() => NORMAL_Y_NEGATIVE,
+            PlatformType::Floor => params.slope.floor_normal(),
+            PlatformType::Ceiling => -params.slope.floor_normal(),
+        };
 
         // Through trial and error i found that it should be enumerated starting from 1.
         // Before, there was texture bleeding, but now no bleeding!
         let mut y_pixel_pos =
             1.0 + draw_from as f32 - self.y_shearing - self.half_view_height;
 
-        for pixel in blueprint {
-            let row_dist = denominator / y_pixel_pos;
-            let pos = ray.origin + row_dist * pos_factor;
-
-            // TODO try removing min and test for speed!!!
-            let tex_x = ((tex_width as f32 * pos.x.fract()) as usize).min(tex_width - 1);
-            let tex_y =
-                ((tex_height as f32 * pos.z.fract()) as usize).min(tex_height - 1);
-            let tex_y = match params.platform_type {
-                PlatformType::Floor => tex_height - tex_y - 1,
-                PlatformType::Ceiling => tex_y,
-            };
-            let i = 4 * (tex_width * tex_y + tex_x); //tex_width * 4 * tex_y + tex_x * 4
-            let color = &texture[i..i + 3];
+        for (y, pixel) in blueprint.enumerate() {
+            let row_dist = denominator / (y_pixel_pos - rise_pixels);
+            y_pixel_pos += 1.0;
 
-            pixel.copy_from_slice(color);
+            // Spans of other tile layers can be drawn in front of the platform
+            if let Some(depth) = depth.as_deref_mut() {
+                let distance = ray.camera_distance(row_dist);
+                if depth[draw_from + y] < distance {
+                    continue;
+                }
+                depth[draw_from + y] = distance;
+            }
+
+            let mut view_dir = row_dist * pos_factor;
+            let pos = ray.origin + view_dir;
+
+            let uv = params.mapping.apply(Vec2::new(pos.x, pos.z));
+            let level = mip_level(row_dist * texels_per_distance);
+            let (texture, filter) = match level {
+                0 => (params.texture, self.texture_filter),
+                _ => (params.texture.mip(level), TextureFilter::Nearest),
+            };
+            let uv_y = match params.platform_type {
+                PlatformType::Floor => uv.y,
+                PlatformType::Ceiling => -uv.y,
+            };
+            let texel =
+                texture.sample(uv.x, uv_y, filter, (ray.column_index, draw_from + y));
 
-            y_pixel_pos += 1.0;
+            view_dir.y = height_at(row_dist) - ray.origin.y;
+            let light =
+                self.surface_light(&ray, params.shading, row_dist, view_dir, normal);
+            self.shade_pixel(pixel, &texel, light, draw_from + y);
         }
         (draw_from, draw_to)
     }
@@ -82,9 +122,13 @@ pub struct PlatformRenderParams<'a> {
     pub ray: Ray,
     pub bottom_draw_bound: usize,
     pub top_draw_bound: usize,
+    /// `Y-level` of the platform plane right below or above the ray origin.
     pub height: f32,
+    pub slope: Slope,
     pub platform_type: PlatformType,
     pub texture: TextureDataRef<'a>,
+    pub mapping: TextureMapping,
+    pub shading: Shading,
 }
 
 pub enum PlatformType {
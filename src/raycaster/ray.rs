@@ -1,9 +1,12 @@
-use crate::map::portal::{Portal, Rotation};
+use crate::map::portal::PortalTransform;
 use crate::raycaster::camera::Camera;
 use glam::{Vec2, Vec3};
 
 use super::{PointXZ, Side};
 
+/// How far the ray is moved past the destination portal side to find the tile behind it.
+const PORTAL_NUDGE: f32 = 0.001;
+
 // TODO maybe rename to `MovingRay`
 #[derive(Debug, Clone, Copy)]
 pub struct Ray {
@@ -24,7 +27,7 @@ pub struct Ray {
     /// the ray travels across the map.
     pub step_z: i64,
     /// X-coordinate on the horizontal camera plane through which the Ray passes.
-    //pub plane_x: f32,
+    pub plane_x: f32,
 
     /// Ray origin.
     pub origin: Vec3,
@@ -52,6 +55,9 @@ pub struct Ray {
     /// Offset which represent where exactly was the wall hit
     /// (at which x coordinate).
     pub wall_offset: f32,
+    /// How many times longer the distances in the current room
+    /// are than in the room of the camera.
+    pub distance_scale: f32,
 }
 
 impl Ray {
@@ -109,7 +115,7 @@ impl Ray {
             delta_dist_z,
             step_x: dir.x.signum() as i64,
             step_z: dir.z.signum() as i64,
-            //plane_x,
+            plane_x,
             origin,
             camera_dir: camera.forward_dir,
             horizontal_plane: camera.horizontal_plane,
@@ -123,6 +129,7 @@ impl Ray {
             hit_wall_side: side,
             wall_side,
             wall_offset,
+            distance_scale: 1.0,
         }
     }
 
@@ -146,82 +153,64 @@ impl Ray {
         ray
     }
 
-    pub fn rotate(&mut self, delta: f32) {
-        match (delta.to_degrees() as i32) {
-            90 | -270 => {
-                // Rotate 90 degrees clockwise
-                self.dir = Vec3::new(self.dir.z, 0.0, -self.dir.x);
-                self.camera_dir = Vec3::new(self.camera_dir.z, 0.0, -self.camera_dir.x);
-                self.horizontal_plane =
-                    Vec3::new(self.horizontal_plane.z, 0.0, -self.horizontal_plane.x);
-
-                std::mem::swap(&mut self.delta_dist_x, &mut self.delta_dist_z);
-                std::mem::swap(&mut self.side_dist_x, &mut self.side_dist_z);
-                self.step_x = self.dir.x.signum() as i64;
-                self.step_z = self.dir.z.signum() as i64;
-                self.hit_wall_side = match self.hit_wall_side {
-                    Side::Vertical => Side::Horizontal,
-                    Side::Horizontal => Side::Vertical,
-                };
-                self.wall_side = match self.wall_side {
-                    WallSide::North => WallSide::East,
-                    WallSide::East => WallSide::South,
-                    WallSide::South => WallSide::West,
-                    WallSide::West => WallSide::North,
-                };
-            }
-            -90 | 270 => {
-                // Rotate 90 degrees anticlockwise
-                self.dir = Vec3::new(-self.dir.z, 0.0, self.dir.x);
-                self.camera_dir = Vec3::new(-self.camera_dir.z, 0.0, self.camera_dir.x);
-                self.horizontal_plane =
-                    Vec3::new(-self.horizontal_plane.z, 0.0, self.horizontal_plane.x);
-
-                std::mem::swap(&mut self.delta_dist_x, &mut self.delta_dist_z);
-                std::mem::swap(&mut self.side_dist_x, &mut self.side_dist_z);
-                self.step_x = self.dir.x.signum() as i64;
-                self.step_z = self.dir.z.signum() as i64;
-                self.hit_wall_side = match self.hit_wall_side {
-                    Side::Vertical => Side::Horizontal,
-                    Side::Horizontal => Side::Vertical,
-                };
-                self.wall_side = match self.wall_side {
-                    WallSide::North => WallSide::West,
-                    WallSide::East => WallSide::North,
-                    WallSide::South => WallSide::East,
-                    WallSide::West => WallSide::South,
-                };
-            }
-            180 | -180 => {
-                // No difference, so the ray should be rotated 180 degrees
-                self.dir = -self.dir;
-                self.camera_dir = -self.camera_dir;
-                self.horizontal_plane = -self.horizontal_plane;
-                self.step_x = -self.step_x;
-                self.step_z = -self.step_z;
-                self.wall_side = match self.wall_side {
-                    WallSide::North => WallSide::South,
-                    WallSide::East => WallSide::West,
-                    WallSide::South => WallSide::North,
-                    WallSide::West => WallSide::East,
-                };
-            }
-            _ => {}
+    /// Returns the normal of the wall which was just hit.
+    pub fn wall_normal(&self) -> Vec3 {
+        match self.hit_wall_side {
+            Side::Vertical if self.dir.x > 0.0 => Vec3::NEG_X,
+            Side::Vertical => Vec3::X,
+            Side::Horizontal if self.dir.z > 0.0 => Vec3::NEG_Z,
+            Side::Horizontal => Vec3::Z,
         }
     }
 
-    pub fn portal_teleport(&mut self, src: Portal, dest: Vec2) {
-        //self.origin = src.teleport_to(self.origin, dest);
+    /// Moves the ray through the portal it just hit into the linked room,
+    /// so it continues from the tile right behind the destination portal.
+    /// Distances are measured in the units of the linked room from then on.
+    pub fn pass_portal(&mut self, transform: &PortalTransform) {
+        let scale = transform.scale();
+        self.origin = transform.position(self.origin);
+        self.dir = transform.direction(self.dir);
+        self.camera_dir = transform.direction(self.camera_dir);
+        self.horizontal_plane = transform.direction(self.horizontal_plane);
+        self.wall_dist *= scale;
+        self.previous_wall_dist *= scale;
+        self.distance_scale *= scale;
 
-        self.next_tile = PointXZ::new(dest.x as i64, dest.y as i64);
-        match self.hit_wall_side {
-            Side::Vertical => {
-                self.side_dist_x -= self.delta_dist_x;
-            }
-            Side::Horizontal => {
-                self.side_dist_z -= self.delta_dist_z;
-            }
-        }
+        self.delta_dist_x = 1.0 / self.dir.x.abs();
+        self.delta_dist_z = 1.0 / self.dir.z.abs();
+        self.step_x = self.dir.x.signum() as i64;
+        self.step_z = self.dir.z.signum() as i64;
+
+        // Hit point lies right on the tile side, so move it a bit into the tile
+        let horizontal_dir = Vec2::new(self.dir.x, self.dir.z).normalize_or_zero();
+        let hit = self.origin + self.dir * self.wall_dist;
+        let tile_x = (hit.x + horizontal_dir.x * PORTAL_NUDGE).floor();
+        let tile_z = (hit.z + horizontal_dir.y * PORTAL_NUDGE).floor();
+        self.next_tile = PointXZ::new(tile_x as i64, tile_z as i64);
+        self.side_dist_x = self.delta_dist_x
+            * if self.dir.x < 0.0 {
+                self.origin.x - tile_x
+            } else {
+                tile_x + 1.0 - self.origin.x
+            };
+        self.side_dist_z = self.delta_dist_z
+            * if self.dir.z < 0.0 {
+                self.origin.z - tile_z
+            } else {
+                tile_z + 1.0 - self.origin.z
+            };
+    }
+
+    /// Converts the distance in the current room into the distance
+    /// in the room of the camera, so distances from all rooms can be compared.
+    pub fn camera_distance(&self, distance: f32) -> f32 {
+        distance / self.distance_scale
+    }
+
+    /// Converts the distance in the room of the camera into
+    /// the distance in the current room, see [`Ray::camera_distance`].
+    pub fn room_distance(&self, camera_distance: f32) -> f32 {
+        camera_distance * self.distance_scale
     }
 }
 
@@ -0,0 +1,150 @@
+use crate::map::tilemap::Sprite;
+use crate::{map::room::RoomRef, textures::TextureDataRef};
+
+use super::{
+    lighting::{Shading, ALPHA_CUTOFF},
+    ray::Ray,
+    DeferredRender, FrameRenderer,
+};
+
+/// Sprites closer than this to the camera plane are not drawn.
+const SPRITE_NEAR_DISTANCE: f32 = 0.05;
+
+impl<'a> FrameRenderer<'a> {
+    /// Finds all sprites of the room which the ray passes through between the
+    /// `enter_dist` and the `exit_dist`. The ray has to be already transformed
+    /// into the room, so sprites seen through portals are placed correctly.
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn collect_sprites(
+        &self,
+        ray: &Ray,
+        room: &RoomRef<'a>,
+        enter_dist: f32,
+        exit_dist: f32,
+        bottom_draw_bound: usize,
+        top_draw_bound: usize,
+        deferred: &mut Vec<DeferredRender<'a>>,
+    ) {
+        let camera_dir = ray.camera_dir;
+        let plane = ray.horizontal_plane;
+        let plane_length = plane.length();
+        let det_recip = 1.0 / (camera_dir.x * plane.z - camera_dir.z * plane.x);
+        let (ambient, fog) = (room.data.ambient_light_intensity(), room.data.fog());
+
+        for &sprite in room.data.sprites() {
+            let relative = sprite.position - ray.origin;
+            // Position in camera space: distance along the camera direction
+            // and offset along the horizontal plane
+            let distance = (relative.x * plane.z - relative.z * plane.x) * det_recip;
+            if distance < SPRITE_NEAR_DISTANCE.max(enter_dist) || distance >= exit_dist {
+                continue;
+            }
+            let offset =
+                (camera_dir.x * relative.z - camera_dir.z * relative.x) * det_recip;
+            let tex_u =
+                (ray.plane_x * distance - offset) * plane_length / sprite.width + 0.5;
+            if !(0.0..1.0).contains(&tex_u) {
+                continue;
+            }
+
+            let (width, depth) = room.tilemap.dimensions_i64();
+            let tile_light = room.tilemap.get_tile_light_unchecked(
+                (sprite.position.x as i64).clamp(0, width - 1) as usize,
+                (sprite.position.z as i64).clamp(0, depth - 1) as usize,
+            );
+            deferred.push(DeferredRender::Sprite(SpriteRenderParams {
+                ray: *ray,
+                bottom_draw_bound,
+                top_draw_bound,
+                sprite,
+                distance,
+                tex_u,
+                texture: self.textures.get_texture_data(sprite.texture),
+                shading: Shading::Lit {
+                    ambient,
+                    baked: tile_light.ground,
+                    fog,
+                },
+            }));
+        }
+    }
+
+    /// Draws the sprite slice only over the pixels which are further away
+    /// than the sprite. Distance of the drawn texels is written into the `depth`,
+    /// which holds the distances in the room of the camera.
+    pub(super) fn render_sprite(
+        &self,
+        params: SpriteRenderParams,
+        depth: &mut [f32],
+        column: &mut [u8],
+    ) {
+        let ray = params.ray;
+        let sprite = params.sprite;
+        let (tex_width, tex_height) = (params.texture.width, params.texture.height);
+
+        let half_sprite_pixel_height = self.half_view_height / params.distance;
+        let pixels_to_bottom = half_sprite_pixel_height
+            * (ray.origin.y - sprite.position.y)
+            - self.y_shearing;
+        let pixels_to_top = half_sprite_pixel_height
+            * (sprite.position.y + sprite.height - ray.origin.y)
+            + self.y_shearing;
+        let full_sprite_pixel_height = pixels_to_top + pixels_to_bottom;
+
+        let draw_from = ((self.half_view_height - pixels_to_bottom) as usize)
+            .clamp(params.bottom_draw_bound, params.top_draw_bound);
+        let draw_to = ((self.half_view_height + pixels_to_top) as usize)
+            .clamp(draw_from, params.top_draw_bound);
+
+        let tex_x = ((params.tex_u * tex_width as f32) as usize).min(tex_width - 1);
+        let tex_y_step = tex_height as f32 / full_sprite_pixel_height;
+        let mut tex_y =
+            (draw_from as f32 + pixels_to_bottom - self.half_view_height) * tex_y_step;
+
+        // Sprites always face the camera
+        let light = self.surface_light(
+            &ray,
+            params.shading,
+            params.distance,
+            ray.dir,
+            -ray.dir.normalize(),
+        );
+
+        let camera_distance = ray.camera_distance(params.distance);
+        let blueprint = column
+            .chunks_exact_mut(3)
+            .zip(depth.iter_mut())
+            .enumerate()
+            .skip(draw_from)
+            .take(draw_to - draw_from);
+
+        for (y, (pixel, pixel_depth)) in blueprint {
+            let tex_y_pos = (tex_y as usize).min(tex_height - 1);
+            tex_y += tex_y_step;
+            if *pixel_depth <= camera_distance {
+                continue;
+            }
+
+            let i = 4 * ((tex_height - tex_y_pos - 1) * tex_width + tex_x);
+            self.shade_texel(pixel, &params.texture, i, light, y);
+            if params.texture.data[i + 3] >= ALPHA_CUTOFF {
+                *pixel_depth = camera_distance;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct SpriteRenderParams<'a> {
+    pub ray: Ray,
+    pub bottom_draw_bound: usize,
+    pub top_draw_bound: usize,
+    pub sprite: Sprite,
+    /// Distance of the sprite from the camera plane.
+    pub distance: f32,
+    /// Horizontal texture coordinate of the drawn sprite slice,
+    /// in range [0.0, 1.0).
+    pub tex_u: f32,
+    pub texture: TextureDataRef<'a>,
+    pub shading: Shading,
+}
@@ -1,6 +1,13 @@
-use crate::textures::{TextureDataRef, TextureID};
+use glam::Vec2;
 
-use super::{ray::Ray, FrameRenderer, Side};
+use crate::map::tilemap::TextureMapping;
+use crate::textures::{mip_level, TextureDataRef, TextureFilter, TextureID};
+
+use super::{
+    lighting::Shading,
+    ray::Ray,
+    FrameRenderer, Side,
+};
 
 impl<'a> FrameRenderer<'a> {
     // TODO test if it's better to pass argument in struct or not.
@@ -11,17 +18,12 @@ impl<'a> FrameRenderer<'a> {
         &self,
         params: WallRenderParams,
         column: &'a mut [u8],
+        mut depth: Option<&mut [f32]>,
     ) -> (usize, usize) {
         let ray = params.ray;
         let bottom_level = params.bottom_level;
         let top_level = params.top_level;
 
-        let (texture, tex_width, tex_height) = (
-            params.texture.data,
-            params.texture.width as usize,
-            params.texture.height as usize,
-        );
-
         // Calculate wall pixel height for the parts above and below the middle
         let half_wall_pixel_height = self.half_view_height / ray.wall_dist;
         let pixels_to_bottom =
@@ -35,19 +37,37 @@ impl<'a> FrameRenderer<'a> {
         let draw_to = ((self.half_view_height + pixels_to_top) as usize)
             .clamp(draw_from, params.top_draw_bound);
 
-        let tex_x = match ray.hit_wall_side {
-            Side::Vertical if ray.dir.x > 0.0 => {
-                tex_width - (ray.wall_offset * tex_width as f32) as usize - 1
-            }
-            Side::Horizontal if ray.dir.z < 0.0 => {
-                tex_width - (ray.wall_offset * tex_width as f32) as usize - 1
-            }
-            _ => (ray.wall_offset * tex_width as f32) as usize,
+        // Texture coordinates along the wall and upwards from the bottom level,
+        // where the texture covers a tile wide and two units high part of the wall
+        let u = match ray.hit_wall_side {
+            Side::Vertical if ray.dir.x > 0.0 => 1.0 - ray.wall_offset,
+            Side::Horizontal if ray.dir.z < 0.0 => 1.0 - ray.wall_offset,
+            _ => ray.wall_offset,
         };
-        let tex_y_step =
-            (top_level - bottom_level) * tex_height as f32 / full_wall_pixel_height * 0.5;
-        let mut tex_y =
-            (draw_from as f32 + pixels_to_bottom - self.half_view_height) * tex_y_step;
+        let v_step = (top_level - bottom_level) / full_wall_pixel_height * 0.5;
+        let v = (draw_from as f32 + pixels_to_bottom - self.half_view_height) * v_step;
+        // Texture coordinates change only with the v coordinate along the column
+        let mut uv = params.mapping.apply(Vec2::new(u, v));
+        let uv_step = params.mapping.matrix * Vec2::new(0.0, v_step);
+
+        // Farther walls are drawn from smaller mips, as more texels fall on a pixel
+        let texels_per_pixel = (uv_step
+            * Vec2::new(params.texture.width as f32, params.texture.height as f32))
+        .length();
+        let level = mip_level(texels_per_pixel);
+        let texture = params.texture.mip(level);
+        let filter = match level {
+            0 => self.texture_filter,
+            _ => TextureFilter::Nearest,
+        };
+
+        let light = self.surface_light(
+            &ray,
+            params.shading,
+            ray.wall_dist,
+            ray.dir,
+            ray.wall_normal(),
+        );
 
         let blueprint = column
             .chunks_exact_mut(3)
@@ -55,20 +75,31 @@ impl<'a> FrameRenderer<'a> {
             .skip(draw_from)
             .take(draw_to - draw_from);
 
-        for (_y, pixel) in blueprint {
-            // avoids small artefacts let tex_y_pos = tex_y.round() as usize % tex_height;
-            let tex_y_pos = tex_y as usize % tex_height;
-            tex_y += tex_y_step;
+        let distance = ray.camera_distance(ray.wall_dist);
+        let is_opaque = texture.transparency.is_opaque();
+        for (y, pixel) in blueprint {
+            let pixel_uv = uv;
+            uv += uv_step;
 
-            let i = 4 * ((tex_height - tex_y_pos - 1) * tex_width + tex_x);
-            let color = &texture[i..i + 3];
+            // Spans of other tile layers can be drawn in front of the wall
+            if let Some(depth) = depth.as_deref_mut() {
+                if depth[y] < distance {
+                    continue;
+                }
+                if is_opaque {
+                    depth[y] = distance;
+                }
+            }
 
-            pixel.copy_from_slice(color);
+            let texel =
+                texture.sample(pixel_uv.x, pixel_uv.y, filter, (ray.column_index, y));
+            self.shade_rgba(pixel, &texel, texture.transparency, light, y);
         }
         (draw_from, draw_to)
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct WallRenderParams<'a> {
     pub ray: Ray,
     pub bottom_draw_bound: usize,
@@ -76,4 +107,6 @@ pub struct WallRenderParams<'a> {
     pub bottom_level: f32,
     pub top_level: f32,
     pub texture: TextureDataRef<'a>,
+    pub mapping: TextureMapping,
+    pub shading: Shading,
 }
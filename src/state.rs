@@ -1,15 +1,19 @@
-use std::path::PathBuf;
+use std::{fmt, path::PathBuf};
 
 use rayon::iter::ParallelIterator;
 use winit::event::DeviceEvent;
 
 use crate::{
+    backend::DebugData,
     control::GameInput,
-    map::{self, room::RoomID, Map},
+    map::{
+        self, generator::GenerationError, parser::ParseError, room::RoomID, Map,
+        MapSettings,
+    },
     models::ModelArray,
     player::Player,
     raycaster::{self, camera::Camera, FrameRenderer},
-    textures::TextureArray,
+    textures::{TextureArray, TextureFilter},
 };
 
 const PHYSICS_TIMESTEP: f32 = 0.01;
@@ -20,10 +24,15 @@ pub struct GameState {
     map: Map,
     textures: TextureArray,
     models: ModelArray,
+    texture_filter: TextureFilter,
 
     player: Player,
+    /// Rooms seen in the last frame, which are kept loaded.
+    seen_rooms: Vec<RoomID>,
 
     delta_accumulator: f32,
+    /// Seconds since the game started, by which the textures are animated.
+    time: f64,
 }
 
 impl GameState {
@@ -31,8 +40,9 @@ impl GameState {
         data_path: P,
         view_width: u32,
         view_height: u32,
-    ) -> Self {
-        let (tile_maps, textures) = map::parser::parse("tiled");
+        map_settings: MapSettings,
+    ) -> Result<Self, LoadError> {
+        let (tile_maps, textures, animations, models) = map::parser::parse("tiled")?;
         /*let input = clean_input(std::fs::read_to_string(path).unwrap());
         let (blueprints, textures, models) = match MapParser::new(parent_path)
             .parse(&input)
@@ -46,48 +56,105 @@ impl GameState {
         };*/
 
         let camera = Camera::new(view_width, view_height);
+        let map = Map::new(tile_maps, map_settings)?;
+        let player = Player::new(RoomID(0), map.spawn());
 
-        Self {
+        Ok(Self {
             camera,
 
-            map: Map::new(tile_maps),
-            textures: TextureArray::new(textures),
-            models: ModelArray::new(vec![]),
+            map,
+            textures: TextureArray::new(textures, animations),
+            models: ModelArray::new(models),
+            texture_filter: TextureFilter::default(),
 
-            player: Player::new(RoomID(0)),
+            player,
+            seen_rooms: Vec::new(),
 
             delta_accumulator: 0.0,
-        }
+            time: 0.0,
+        })
     }
 
-    pub fn update(&mut self, delta: f32) {
+    /// Fails if the map can't be generated any further.
+    pub fn update(&mut self, delta: f32) -> Result<(), GenerationError> {
         // Update world and player
         self.delta_accumulator += delta;
         while self.delta_accumulator >= PHYSICS_TIMESTEP {
+            self.map.update_movers(
+                PHYSICS_TIMESTEP,
+                self.player.current_room_id(),
+                self.player.feet_position(),
+            );
             self.player.update(&self.map, PHYSICS_TIMESTEP);
             self.delta_accumulator -= PHYSICS_TIMESTEP;
         }
+        self.map
+            .update(self.player.current_room_id(), &self.seen_rooms)?;
         self.camera.follow(self.player.get_camera_target());
+        self.time += delta as f64;
+        self.textures.update(self.time);
         //self.world.update(&mut self.player);
+        Ok(())
     }
 
-    pub fn render<'a>(&mut self, canvas: &mut [u8]) {
-        FrameRenderer::new(&self.camera, &self.player, &self.map, &self.textures)
-            .render(canvas);
+    /// Fails if the map can't be generated any further.
+    pub fn render<'a>(&mut self, canvas: &mut [u8]) -> Result<(), GenerationError> {
+        let seen = FrameRenderer::new(
+            &self.camera,
+            &self.player,
+            &self.map,
+            &self.textures,
+            &self.models,
+            self.texture_filter,
+        )
+        .render(canvas);
+        // Rooms seen through unlinked portals get linked for the next frame
+        self.seen_rooms = seen.rooms;
+        self.map
+            .generate_seen_rooms(self.player.current_room_id(), &seen.dead_ends)
     }
 
-    pub fn render_par<'a>(&mut self, canvas: &mut [u8]) {
-        FrameRenderer::new(&self.camera, &self.player, &self.map, &self.textures)
-            .render_par(canvas);
+    /// Fails if the map can't be generated any further.
+    pub fn render_par<'a>(&mut self, canvas: &mut [u8]) -> Result<(), GenerationError> {
+        let seen = FrameRenderer::new(
+            &self.camera,
+            &self.player,
+            &self.map,
+            &self.textures,
+            &self.models,
+            self.texture_filter,
+        )
+        .render_par(canvas);
+        self.seen_rooms = seen.rooms;
+        self.map
+            .generate_seen_rooms(self.player.current_room_id(), &seen.dead_ends)
     }
 
     pub fn handle_game_input(&mut self, input: GameInput, is_pressed: bool) {
         match input {
             GameInput::ReloadMap => {
-                let (tile_maps, textures) = map::parser::parse("tiled");
-                self.map = Map::new(tile_maps);
-                self.textures = TextureArray::new(textures);
-            },
+                let reloaded = map::parser::parse("tiled")
+                    .map_err(LoadError::from)
+                    .and_then(|(tile_maps, textures, animations, models)| {
+                        let map = Map::new(tile_maps, self.map.settings())?;
+                        Ok((map, textures, animations, models))
+                    });
+                match reloaded {
+                    Ok((map, textures, animations, models)) => {
+                        // Rooms of the previous map are gone, so start over
+                        self.player = Player::new(RoomID(0), map.spawn());
+                        self.map = map;
+                        self.textures = TextureArray::new(textures, animations);
+                        self.models = ModelArray::new(models);
+                    }
+                    // Keep playing on the previous map until the error is fixed
+                    Err(e) => log::error!("Failed to reload the map: {}", e),
+                }
+            }
+            GameInput::CycleTextureFilter if !is_pressed => {
+                self.texture_filter = self.texture_filter.next();
+                log::info!("Texture filter: {:?}", self.texture_filter);
+            }
             _ => ()
         }
         self.player.handle_game_input(input, is_pressed);
@@ -105,15 +172,39 @@ impl GameState {
         self.camera = Camera::new(view_width, view_height);
     }
 
-    /*pub fn collect_dbg_data(&self) -> DebugData {
-        let player_dbg_data = self.player.collect_dbg_data();
-        //let world_dbg_data = WorldDebugData {
-        //    room_count: 0,
-        //};
-
+    pub fn collect_dbg_data(&self) -> DebugData {
         DebugData {
-            player_data: player_dbg_data,
-            //world_data: world_dbg_data,
+            player_data: self.player.collect_dbg_data(),
+            map_data: self.map.collect_dbg_data(),
+            y_shearing: self.camera.y_shearing,
         }
-    }*/
+    }
+}
+
+/// Error which prevents the game from loading the map.
+#[derive(Debug)]
+pub enum LoadError {
+    Parse(ParseError),
+    Generation(GenerationError),
+}
+
+impl From<ParseError> for LoadError {
+    fn from(e: ParseError) -> Self {
+        Self::Parse(e)
+    }
+}
+
+impl From<GenerationError> for LoadError {
+    fn from(e: GenerationError) -> Self {
+        Self::Generation(e)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Parse(e) => write!(f, "{}", e),
+            LoadError::Generation(e) => write!(f, "{}", e),
+        }
+    }
 }
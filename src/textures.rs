@@ -4,23 +4,85 @@ use crate::map::tilemap::Skybox;
 
 pub struct TextureArray {
     textures: Vec<TextureData>,
+    /// Animated textures, which take the IDs after the textures.
+    animations: Vec<TextureAnimation>,
+    /// Frame which each texture ID shows at the current time.
+    frames: Vec<TextureFrame>,
 }
 
 impl TextureArray {
-    pub(super) fn new(mut textures: Vec<TextureData>) -> Self {
-        let default_texture = TextureData {
-            id: DEFAULT_TEXTURE_ID,
-            data: DEFAULT_TEXTURE_RGBA.to_vec(),
-            width: DEFAULT_TEXTURE_WIDTH,
-            height: DEFAULT_TEXTURE_HEIGHT,
-            transparency: DEFAULT_TEXTURE_TRANSPARENCY,
-        };
+    pub(super) fn new(
+        mut textures: Vec<TextureData>,
+        animations: Vec<TextureAnimation>,
+    ) -> Self {
+        let default_texture = TextureData::new(
+            String::new(),
+            DEFAULT_TEXTURE_RGBA.to_vec(),
+            DEFAULT_TEXTURE_WIDTH,
+            DEFAULT_TEXTURE_HEIGHT,
+            DEFAULT_TEXTURE_TRANSPARENCY,
+        );
         textures.insert(0, default_texture);
+        // Frames of the strips are mipmapped apart, so they don't blend
+        for animation in &animations {
+            if let TextureAnimation::Strip { texture, .. } = animation {
+                let strip = &mut textures[texture.0];
+                strip.set_frame_height(strip.width);
+            }
+        }
 
-        Self { textures }
+        let frames = textures
+            .iter()
+            .enumerate()
+            .map(|(index, texture)| TextureFrame {
+                index,
+                first_row: 0,
+                rows: texture.height,
+            })
+            .collect();
+        let mut texture_array = Self {
+            textures,
+            animations,
+            frames,
+        };
+        texture_array.update(0.0);
+        texture_array
     }
 
-    pub fn get_skybox_textures(&self, skybox: &Skybox) -> SkyboxTexturesRef {
+    /// Moves all animated textures to their frames at the `time` in seconds.
+    pub fn update(&mut self, time: f64) {
+        self.frames.truncate(self.textures.len());
+        // Animations only show textures with lower IDs, which are already updated
+        for animation in &self.animations {
+            let frame = match animation {
+                TextureAnimation::Strip { texture, fps } => {
+                    let strip = &self.textures[texture.0];
+                    let frame_count = (strip.height / strip.width).max(1);
+                    let frame = (time * *fps as f64) as usize % frame_count;
+                    TextureFrame {
+                        index: texture.0,
+                        first_row: frame * strip.width,
+                        rows: strip.width.min(strip.height),
+                    }
+                }
+                TextureAnimation::Frames(frames) => {
+                    let duration: f32 = frames.iter().map(|(_, duration)| duration).sum();
+                    let mut left = (time % duration.max(f32::EPSILON) as f64) as f32;
+                    let (texture, _) = frames
+                        .iter()
+                        .find(|(_, duration)| {
+                            left -= duration;
+                            left < 0.0
+                        })
+                        .unwrap_or(&frames[frames.len() - 1]);
+                    self.frames[texture.0]
+                }
+            };
+            self.frames.push(frame);
+        }
+    }
+
+    pub fn get_skybox_textures(&self, skybox: &Skybox) -> SkyboxTexturesRef<'_> {
         SkyboxTexturesRef {
             north: self.get_texture_data(skybox.north),
             east: self.get_texture_data(skybox.east),
@@ -31,56 +93,92 @@ impl TextureArray {
         }
     }
 
-    pub(super) fn get_texture_data(&self, id: TextureID) -> TextureDataRef {
-        self.textures[id.0].as_ref()
+    /// Animated textures give their current frame.
+    pub(super) fn get_texture_data(&self, id: TextureID) -> TextureDataRef<'_> {
+        let frame = self.frames[id.0];
+        self.textures[frame.index].rows(frame.first_row, frame.rows)
     }
 }
 
 // TODO maybe rename to TextureID
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct TextureID(pub usize);
 
-impl Default for TextureID {
-    fn default() -> Self {
-        Self(0)
-    }
+/// Texture which changes with time, by showing other textures or
+/// parts of a texture one after another in a loop.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextureAnimation {
+    /// Textures shown for their durations in seconds.
+    Frames(Vec<(TextureID, f32)>),
+    /// Vertical strip of square frames, from the top one to the
+    /// bottom one, played at the frames per second.
+    Strip { texture: TextureID, fps: f32 },
+}
+
+/// Rows of a texture which a texture ID shows.
+#[derive(Debug, Clone, Copy)]
+struct TextureFrame {
+    index: usize,
+    first_row: usize,
+    rows: usize,
 }
 
 pub struct TextureData {
-    id: TextureID,
+    /// File name of the texture.
+    name: String,
     data: Vec<u8>,
     width: usize,
     height: usize,
-    transparency: bool,
+    transparency: Transparency,
+    /// Height of each frame if the texture is a strip of frames,
+    /// or the height of the texture otherwise.
+    frame_height: usize,
+    /// Smaller copies of the texture, each half the size of the previous
+    /// one down to a single texel, for drawing the texture from afar.
+    mips: Vec<Vec<u8>>,
 }
 
 impl TextureData {
     pub fn new(
-        id: TextureID,
+        name: String,
         data: Vec<u8>,
         width: usize,
         height: usize,
-        transparency: bool,
+        transparency: Transparency,
     ) -> Self {
+        let mips = build_mips(&data, width, height, height);
         Self {
-            id,
+            name,
             data,
             width,
             height,
             transparency,
+            frame_height: height,
+            mips,
         }
     }
 
-    pub fn id(&self) -> TextureID {
-        self.id
+    /// Splits the texture into frames of the height, which get their own mips.
+    fn set_frame_height(&mut self, frame_height: usize) {
+        self.frame_height = frame_height.clamp(1, self.height);
+        self.mips = build_mips(&self.data, self.width, self.height, self.frame_height);
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
-    fn as_ref(&self) -> TextureDataRef {
+    /// Returns the `rows` long part of the texture from the `first_row`.
+    fn rows(&self, first_row: usize, rows: usize) -> TextureDataRef<'_> {
         TextureDataRef {
-            data: &self.data,
+            data: &self.data
+                [first_row * self.width * 4..(first_row + rows) * self.width * 4],
             width: self.width,
-            height: self.height,
+            height: rows,
             transparency: self.transparency,
+            mips: &self.mips,
+            first_row,
+            frame_height: self.frame_height,
         }
     }
 }
@@ -89,6 +187,7 @@ impl TextureData {
 impl Debug for TextureData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("TextureData")
+            .field("name", &self.name)
             .field("data size: {}", &self.data.len())
             .field(
                 "color channels: {}",
@@ -106,7 +205,232 @@ pub struct TextureDataRef<'a> {
     pub data: &'a [u8],
     pub width: usize,
     pub height: usize,
-    pub transparency: bool,
+    pub transparency: Transparency,
+    /// Mips of the whole texture, of which only the same frames are shown.
+    mips: &'a [Vec<u8>],
+    first_row: usize,
+    frame_height: usize,
+}
+
+impl<'a> TextureDataRef<'a> {
+    /// Returns the texture `level` times halved in size, or the smallest one.
+    pub fn mip(&self, level: usize) -> TextureDataRef<'a> {
+        let level = level.min(self.mips.len());
+        if level == 0 {
+            return *self;
+        }
+        let data = &self.mips[level - 1];
+        let width = (self.width >> level).max(1);
+        // Each frame is halved on its own, and the shown rows start at a frame
+        let frame_height = (self.frame_height >> level).max(1);
+        let first_row = self.first_row / self.frame_height * frame_height;
+        let height = (self.height.div_ceil(self.frame_height) * frame_height)
+            .min(data.len() / (width * 4) - first_row);
+        TextureDataRef {
+            data: &data[first_row * width * 4..(first_row + height) * width * 4],
+            width,
+            height,
+            transparency: self.transparency,
+            mips: &[],
+            first_row: 0,
+            frame_height: height,
+        }
+    }
+
+    /// Returns the RGBA texel at the texture coordinates, in which the texture is
+    /// one unit big, repeats and the y coordinate points up. Dithering changes
+    /// with the position of the drawn `pixel` on the screen.
+    #[inline]
+    pub fn sample(
+        &self,
+        x: f32,
+        y: f32,
+        filter: TextureFilter,
+        pixel: (usize, usize),
+    ) -> [u8; 4] {
+        let tex_x = (x - x.floor()) * self.width as f32;
+        let tex_y = (y - y.floor()) * self.height as f32;
+        match filter {
+            TextureFilter::Nearest => self.texel(tex_x as isize, tex_y as isize),
+            TextureFilter::Dithered => {
+                let (offset_x, offset_y) = DITHER_KERNEL[pixel.1 & 1][pixel.0 & 1];
+                self.texel((tex_x + offset_x) as isize, (tex_y + offset_y) as isize)
+            }
+            TextureFilter::Bilinear => {
+                let (tex_x, tex_y) = (tex_x - 0.5, tex_y - 0.5);
+                let (x0, y0) = (tex_x.floor(), tex_y.floor());
+                let (fract_x, fract_y) = (tex_x - x0, tex_y - y0);
+                let (x0, y0) = (x0 as isize, y0 as isize);
+                let bottom_left = self.texel(x0, y0);
+                let bottom_right = self.texel(x0 + 1, y0);
+                let top_left = self.texel(x0, y0 + 1);
+                let top_right = self.texel(x0 + 1, y0 + 1);
+                std::array::from_fn(|i| {
+                    let bottom = bottom_left[i] as f32
+                        + (bottom_right[i] as f32 - bottom_left[i] as f32) * fract_x;
+                    let top = top_left[i] as f32
+                        + (top_right[i] as f32 - top_left[i] as f32) * fract_x;
+                    (bottom + (top - bottom) * fract_y) as u8
+                })
+            }
+        }
+    }
+
+    /// Returns the texel in the column `x` and the row `y` counted
+    /// from the bottom, where the texture repeats in both directions.
+    #[inline]
+    fn texel(&self, x: isize, y: isize) -> [u8; 4] {
+        let x = x.rem_euclid(self.width as isize) as usize;
+        let y = self.height - 1 - y.rem_euclid(self.height as isize) as usize;
+        let i = 4 * (y * self.width + x);
+        [
+            self.data[i],
+            self.data[i + 1],
+            self.data[i + 2],
+            self.data[i + 3],
+        ]
+    }
+}
+
+/// Returns the mip level with about one texel per drawn pixel, for a texture
+/// drawn with `texels_per_pixel` of its full size.
+#[inline]
+pub fn mip_level(texels_per_pixel: f32) -> usize {
+    (texels_per_pixel as u32).max(1).ilog2() as usize
+}
+
+/// How texels are picked where a texture is drawn bigger than a texel per pixel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TextureFilter {
+    /// The closest texel, so the texels look like blocks up close.
+    #[default]
+    Nearest,
+    /// The closest texel to a point moved by a pattern which repeats every
+    /// two pixels, which looks like a blend of the closest texels from afar.
+    Dithered,
+    /// Blend of the four closest texels.
+    Bilinear,
+}
+
+impl TextureFilter {
+    /// Returns the filter which is switched to after this one.
+    pub fn next(self) -> Self {
+        match self {
+            TextureFilter::Nearest => TextureFilter::Dithered,
+            TextureFilter::Dithered => TextureFilter::Bilinear,
+            TextureFilter::Bilinear => TextureFilter::Nearest,
+        }
+    }
+}
+
+/// Offsets in texels by which the dithered filter moves
+/// the sampled point, for every other row and column.
+const DITHER_KERNEL: [[(f32, f32); 2]; 2] =
+    [[(0.25, 0.0), (0.5, 0.75)], [(0.75, 0.5), (0.0, 0.25)]];
+
+/// Returns the mips of the RGBA data, where each `frame_height` rows tall frame
+/// is halved on its own, so the frames of a strip never blend into each other.
+/// Each level holds the frames one after another, the last one can be shorter.
+fn build_mips(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    frame_height: usize,
+) -> Vec<Vec<u8>> {
+    let mut frame_heights: Vec<usize> = (0..height)
+        .step_by(frame_height)
+        .map(|row| frame_height.min(height - row))
+        .collect();
+    let mut mips: Vec<Vec<u8>> = Vec::new();
+    let (mut mip_width, mut mip_frame_height) = (width, frame_height);
+    while mip_width > 1 || mip_frame_height > 1 {
+        let previous = mips.last().map_or(data, Vec::as_slice);
+        let mut next = Vec::new();
+        let mut row = 0;
+        for frame_height in &mut frame_heights {
+            let frame =
+                &previous[row * mip_width * 4..(row + *frame_height) * mip_width * 4];
+            downsample(frame, mip_width, *frame_height, &mut next);
+            row += *frame_height;
+            *frame_height = (*frame_height / 2).max(1);
+        }
+        mips.push(next);
+        (mip_width, mip_frame_height) =
+            ((mip_width / 2).max(1), (mip_frame_height / 2).max(1));
+    }
+    mips
+}
+
+/// Adds the RGBA data with the size halved by averaging the blocks of texels to
+/// `next`. Blocks at the edge of textures with odd sizes also take the last row
+/// or column. Colors are weighted by alpha, so transparent texels don't darken.
+fn downsample(data: &[u8], width: usize, height: usize, next: &mut Vec<u8>) {
+    let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+    next.reserve(next_width * next_height * 4);
+    for y in 0..next_height {
+        let rows = y * 2..(y * 2 + 2).min(height);
+        for x in 0..next_width {
+            let columns = x * 2..(x * 2 + 2).min(width);
+            let mut color_sum = [0u32; 3];
+            let mut plain_sum = [0u32; 3];
+            let mut alpha_sum = 0;
+            let mut count = 0;
+            for row in rows.clone() {
+                for column in columns.clone() {
+                    let i = 4 * (row * width + column);
+                    let alpha = data[i + 3] as u32;
+                    for channel in 0..3 {
+                        color_sum[channel] += data[i + channel] as u32 * alpha;
+                        plain_sum[channel] += data[i + channel] as u32;
+                    }
+                    alpha_sum += alpha;
+                    count += 1;
+                }
+            }
+            // Fully transparent blocks keep their plain color average
+            let color = match alpha_sum {
+                0 => plain_sum.map(|channel| channel / count),
+                _ => color_sum.map(|channel| channel / alpha_sum),
+            };
+            next.extend(color.map(|channel| channel as u8));
+            next.push((alpha_sum / count) as u8);
+        }
+    }
+}
+
+/// How the alpha channel of a texture should be treated while drawing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transparency {
+    /// Every texel is fully opaque so the alpha channel can be ignored.
+    Opaque,
+    /// Texels are either fully opaque or fully transparent, so they are
+    /// drawn or skipped depending on the alpha cutoff.
+    Masked,
+    /// Texels have partial transparency and have to be blended
+    /// with whatever is behind them.
+    Translucent,
+}
+
+impl Transparency {
+    /// Finds the kind of transparency from the alpha channel of RGBA data.
+    pub fn from_rgba(data: &[u8]) -> Self {
+        data.chunks_exact(4)
+            .map(|texel| match texel[3] {
+                255 => Self::Opaque,
+                0 => Self::Masked,
+                _ => Self::Translucent,
+            })
+            .fold(Self::Opaque, |acc, texel| match (acc, texel) {
+                (Self::Translucent, _) | (_, Self::Translucent) => Self::Translucent,
+                (Self::Masked, _) | (_, Self::Masked) => Self::Masked,
+                _ => Self::Opaque,
+            })
+    }
+
+    #[inline]
+    pub fn is_opaque(self) -> bool {
+        self == Self::Opaque
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -119,10 +443,101 @@ pub struct SkyboxTexturesRef<'a> {
     pub bottom: TextureDataRef<'a>,
 }
 
-const DEFAULT_TEXTURE_ID: TextureID = TextureID(0);
 const DEFAULT_TEXTURE_WIDTH: usize = 2;
 const DEFAULT_TEXTURE_HEIGHT: usize = 2;
 const DEFAULT_TEXTURE_RGBA: [u8; 16] = [
     200, 0, 200, 255, 0, 0, 0, 255, 0, 0, 0, 255, 200, 0, 200, 255,
 ];
-const DEFAULT_TEXTURE_TRANSPARENCY: bool = false;
+const DEFAULT_TEXTURE_TRANSPARENCY: Transparency = Transparency::Opaque;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn animations_pick_frames_by_time() {
+        // 1x3 strip with a different red value in each row
+        let strip = TextureData::new(
+            "strip".to_owned(),
+            vec![0, 0, 0, 255, 1, 0, 0, 255, 2, 0, 0, 255],
+            1,
+            3,
+            Transparency::Opaque,
+        );
+        let animations = vec![
+            TextureAnimation::Strip {
+                texture: TextureID(1),
+                fps: 2.0,
+            },
+            TextureAnimation::Frames(vec![(TextureID(0), 1.0), (TextureID(2), 0.5)]),
+        ];
+        let mut textures = TextureArray::new(vec![strip], animations);
+        let red = |textures: &TextureArray, id| {
+            textures.get_texture_data(TextureID(id)).data[0]
+        };
+
+        assert_eq!(textures.get_texture_data(TextureID(2)).height, 1);
+        for (time, strip_frame) in [(0.0, 0), (0.6, 1), (1.2, 2), (1.6, 0)] {
+            textures.update(time);
+            assert_eq!(red(&textures, 2), strip_frame);
+        }
+        // Frames can show other animations
+        textures.update(0.9);
+        assert_eq!(red(&textures, 3), DEFAULT_TEXTURE_RGBA[0]);
+        textures.update(1.2);
+        assert_eq!(red(&textures, 3), 2);
+    }
+
+    #[test]
+    fn mips_average_texels_down_to_one() {
+        // 2x4 texture with a different red value in each texel
+        let data = (0..8).flat_map(|red| [red * 10, 0, 0, 255]).collect();
+        let texture =
+            TextureData::new("texture".to_owned(), data, 2, 4, Transparency::Opaque);
+        let texture = texture.rows(0, 4);
+
+        let half = texture.mip(1);
+        assert_eq!((half.width, half.height), (1, 2));
+        assert_eq!([half.data[0], half.data[4]], [15, 55]);
+        let smallest = texture.mip(5);
+        assert_eq!((smallest.width, smallest.height), (1, 1));
+        assert_eq!(smallest.data[0], 35);
+
+        assert_eq!(mip_level(0.5), 0);
+        assert_eq!(mip_level(3.0), 1);
+        assert_eq!(mip_level(4.0), 2);
+        // Rows are counted from the bottom
+        let nearest = texture.sample(0.75, 0.1, TextureFilter::Nearest, (0, 0));
+        assert_eq!(nearest[0], 70);
+        let bilinear = texture.sample(0.5, 0.125, TextureFilter::Bilinear, (0, 0));
+        assert_eq!(bilinear[0], 65);
+
+        // Transparent texels don't darken the color of the texels next to them
+        let data = [255, 255, 255, 255].into_iter().chain([0; 12]).collect();
+        let texture =
+            TextureData::new("texture".to_owned(), data, 2, 2, Transparency::Masked);
+        assert_eq!(texture.rows(0, 2).mip(1).data, [255, 255, 255, 63]);
+    }
+
+    #[test]
+    fn strip_mips_keep_frames_apart() {
+        // 3x9 strip of three frames with a different red value in each
+        let data = (0..27)
+            .flat_map(|texel| [(texel / 9 + 1) * 10, 0, 0, 255])
+            .collect();
+        let strip =
+            TextureData::new("strip".to_owned(), data, 3, 9, Transparency::Opaque);
+        let animations = vec![TextureAnimation::Strip {
+            texture: TextureID(1),
+            fps: 1.0,
+        }];
+        let mut textures = TextureArray::new(vec![strip], animations);
+
+        for frame in 0..3 {
+            textures.update(frame as f64);
+            let mip = textures.get_texture_data(TextureID(2)).mip(1);
+            assert_eq!((mip.width, mip.height), (1, 1));
+            assert_eq!(mip.data[0], (frame + 1) * 10);
+        }
+    }
+}
@@ -36,7 +36,7 @@
    <property name="ground_height" type="float" value="0"/>
    <property name="ground_texture" value=""/>
    <property name="portal_direction" value="E"/>
-   <property name="top_height" type="float" value="4"/>
+   <property name="top_height" type="float" value="20"/>
    <property name="top_texture" value=""/>
   </properties>
  </tile>
@@ -49,7 +49,7 @@
    <property name="ground_height" type="float" value="0"/>
    <property name="ground_texture" value=""/>
    <property name="portal_direction" value="N"/>
-   <property name="top_height" type="float" value="4"/>
+   <property name="top_height" type="float" value="20"/>
    <property name="top_texture" value=""/>
   </properties>
  </tile>
@@ -62,7 +62,7 @@
    <property name="ground_height" type="float" value="0"/>
    <property name="ground_texture" value=""/>
    <property name="portal_direction" value="W"/>
-   <property name="top_height" type="float" value="4"/>
+   <property name="top_height" type="float" value="20"/>
    <property name="top_texture" value=""/>
   </properties>
  </tile>
@@ -75,7 +75,7 @@
    <property name="ground_height" type="float" value="0"/>
    <property name="ground_texture" value=""/>
    <property name="portal_direction" value="S"/>
-   <property name="top_height" type="float" value="4"/>
+   <property name="top_height" type="float" value="20"/>
    <property name="top_texture" value=""/>
   </properties>
  </tile>
@@ -21,7 +21,7 @@
    <property name="ceiling_height" type="float" value="1000"/>
    <property name="ceiling_texture" value=""/>
    <property name="ground_height" type="float" value="0"/>
-   <property name="ground_texture" value="stone..jpg"/>
+   <property name="ground_texture" value="stone.jpg"/>
    <property name="portal_direction" value=""/>
    <property name="top_height" type="float" value="1001"/>
    <property name="top_texture" value=""/>
@@ -68,7 +68,7 @@
  </tile>
  <tile id="5">
   <properties>
-   <property name="bottom_height" type="float" value="-1"/>
+   <property name="bottom_height" type="float" value="-1.7"/>
    <property name="bottom_texture" value=""/>
    <property name="ceiling_height" type="float" value="1000"/>
    <property name="ceiling_texture" value=""/>
@@ -272,7 +272,7 @@
  </tile>
  <tile id="21">
   <properties>
-   <property name="bottom_height" type="float" value="-1"/>
+   <property name="bottom_height" type="float" value="-1.7"/>
    <property name="bottom_texture" value=""/>
    <property name="ceiling_height" type="float" value="1000"/>
    <property name="ceiling_texture" value=""/>